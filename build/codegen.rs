@@ -151,7 +151,7 @@ pub fn generate_opcode_instructions(opcode_table_path: &Path) -> String {
 fn generate_opcode_body(entry: &OpcodeEntry, opcode: &str) -> TokenStream {
     match entry.mnemonic.as_str() {
         "NOP" => quote! {},
-        "STOP" => quote! {},
+        "STOP" => handle_stop(entry),
         "HALT" => handle_halt(entry),
         "LD" => handle_load_instruction(entry),
         "INC" => handle_inc_dec_instruction(entry),
@@ -919,6 +919,14 @@ fn handle_halt(entry: &OpcodeEntry) -> TokenStream {
     }
 }
 
+fn handle_stop(entry: &OpcodeEntry) -> TokenStream {
+    assert!(entry.mnemonic == "STOP");
+    // Only the CGB speed switch is emulated, otherwise STOP is treated as a NOP
+    quote! {
+        self.mmu.switch_speed();
+    }
+}
+
 // CB prefix
 fn handle_cb(entry: &OpcodeEntry) -> TokenStream {
     assert!(entry.mnemonic == "PREFIX");
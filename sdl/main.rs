@@ -1,4 +1,5 @@
 use std::{
+    fs,
     path::PathBuf,
     process::exit,
     thread,
@@ -7,14 +8,20 @@ use std::{
 
 use clap::Parser;
 use gb_emulator::{
-    cartridge::Cartridge,
+    apu::AUDIO_CHANNELS,
+    boot::{BootRom, Model, compat_palettes::ManualPalette},
+    cartridge::{Cartridge, clock::WallClock, image_source::StaticImage},
     cpu::Cycles,
     gb::{GBButton, GameBoy, JoypadButton, JoypadDpad},
     ppu::{GB_SCREEN_HEIGHT, GB_SCREEN_WIDTH},
+    sgb::{SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH},
 };
 use sdl2::{
+    EventPump,
+    audio::{AudioQueue, AudioSpecDesired},
     event::{Event, WindowEvent},
-    keyboard::Keycode,
+    keyboard::{Keycode, Scancode},
+    mouse::MouseButton,
     pixels::PixelFormatEnum,
     rect::Rect,
     render::TextureAccess,
@@ -27,16 +34,45 @@ pub struct Args {
 
     #[arg(short, long)]
     pub print_serial: bool,
+
+    /// IPS, UPS or BPS patch to apply, instead of one next to the ROM
+    #[arg(long)]
+    pub patch: Option<PathBuf>,
+
+    /// PNG shown to the Pocket Camera instead of the test pattern
+    #[arg(long)]
+    pub camera_image: Option<PathBuf>,
+
+    /// Hardware to emulate: dmg, mgb, sgb or cgb
+    #[arg(long, default_value = "dmg", value_parser = parse_model)]
+    pub model: Model,
+
+    /// Colors for DMG games on the CGB: brown, red, dark-brown, blue, dark-blue, grayscale,
+    /// pastel, orange, yellow, green, dark-green or inverted. Picked from the header by default.
+    #[arg(long, value_parser = parse_palette)]
+    pub palette: Option<ManualPalette>,
+
+    /// Boot ROM to run before the cartridge, instead of skipping straight to it
+    #[arg(long)]
+    pub boot_rom: Option<PathBuf>,
 }
 
 // Game Boy hardware constants
 const CPU_CYCLES_PER_SECOND: u32 = 4_194_304;
-const FPS: u32 = 60;
-const CYCLES_PER_FRAME: u32 = (CPU_CYCLES_PER_SECOND as f64 / FPS as f64) as u32;
-const FRAME_DURATION: Duration = Duration::from_nanos((1_000_000_000u32 / FPS) as u64);
+const CYCLES_PER_FRAME: u32 = 70224; // 154 scanlines of 456 cycles, ~59.73 FPS
+const FRAME_DURATION: Duration =
+    Duration::from_nanos(CYCLES_PER_FRAME as u64 * 1_000_000_000 / CPU_CYCLES_PER_SECOND as u64);
+
+const SAMPLE_RATE: i32 = 48000;
+const AUDIO_BUFFER_SIZE: u16 = 1024;
+// How many stereo samples to keep queued ahead of playback, about 50ms
+const AUDIO_QUEUE_TARGET: u32 = SAMPLE_RATE as u32 / 20;
 
-fn get_screen_rect(win_w: u32, win_h: u32) -> Rect {
-    let gb_aspect_ratio = GB_SCREEN_WIDTH as f32 / GB_SCREEN_HEIGHT as f32;
+// How often battery backed RAM is flushed to the save file
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+fn get_screen_rect(win_w: u32, win_h: u32, (screen_w, screen_h): (usize, usize)) -> Rect {
+    let gb_aspect_ratio = screen_w as f32 / screen_h as f32;
     let win_aspect_ratio = win_w as f32 / win_h as f32;
 
     let (w, h) = if win_aspect_ratio > gb_aspect_ratio {
@@ -55,26 +91,196 @@ fn get_screen_rect(win_w: u32, win_h: u32) -> Rect {
     Rect::new(x as i32, y as i32, w, h)
 }
 
+/// Tilt for cartridges with an accelerometer. The arrow keys tilt fully in each direction,
+/// and holding the right mouse button tilts towards the cursor's position on the screen.
+fn read_tilt(event_pump: &EventPump, screen_rect: Rect) -> (f32, f32) {
+    let mouse = event_pump.mouse_state();
+    if mouse.is_mouse_button_pressed(MouseButton::Right) {
+        let x = (mouse.x() - screen_rect.center().x()) as f32 / (screen_rect.width() / 2) as f32;
+        let y = (mouse.y() - screen_rect.center().y()) as f32 / (screen_rect.height() / 2) as f32;
+        return (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0));
+    }
+
+    let keyboard = event_pump.keyboard_state();
+    let axis = |negative, positive| {
+        keyboard.is_scancode_pressed(positive) as i8 as f32
+            - keyboard.is_scancode_pressed(negative) as i8 as f32
+    };
+    (
+        axis(Scancode::Left, Scancode::Right),
+        axis(Scancode::Up, Scancode::Down),
+    )
+}
+
+/// Palette picked by the keys held while the emulator starts, like holding a direction,
+/// and optionally A or B, during the CGB boot logo does.
+fn held_palette(event_pump: &mut EventPump) -> Option<ManualPalette> {
+    event_pump.pump_events();
+    let keyboard = event_pump.keyboard_state();
+
+    let dpad = [
+        (Scancode::W, JoypadDpad::Up),
+        (Scancode::A, JoypadDpad::Left),
+        (Scancode::S, JoypadDpad::Down),
+        (Scancode::D, JoypadDpad::Right),
+    ]
+    .into_iter()
+    .find(|(scancode, _)| keyboard.is_scancode_pressed(*scancode))?
+    .1;
+    let button = [
+        (Scancode::J, JoypadButton::A),
+        (Scancode::K, JoypadButton::B),
+    ]
+    .into_iter()
+    .find(|(scancode, _)| keyboard.is_scancode_pressed(*scancode))
+    .map(|(_, button)| button);
+
+    ManualPalette::from_buttons(dpad, button)
+}
+
+fn parse_model(model: &str) -> Result<Model, String> {
+    match model.to_ascii_lowercase().as_str() {
+        "dmg" => Ok(Model::DMG),
+        "mgb" => Ok(Model::MGB),
+        "sgb" => Ok(Model::SGB),
+        "cgb" => Ok(Model::CGB),
+        _ => Err(format!("unknown model {}", model)),
+    }
+}
+
+fn parse_palette(palette: &str) -> Result<ManualPalette, String> {
+    match palette.to_ascii_lowercase().as_str() {
+        "brown" => Ok(ManualPalette::Brown),
+        "red" => Ok(ManualPalette::Red),
+        "dark-brown" => Ok(ManualPalette::DarkBrown),
+        "blue" => Ok(ManualPalette::Blue),
+        "dark-blue" => Ok(ManualPalette::DarkBlue),
+        "grayscale" => Ok(ManualPalette::Grayscale),
+        "pastel" => Ok(ManualPalette::Pastel),
+        "orange" => Ok(ManualPalette::Orange),
+        "yellow" => Ok(ManualPalette::Yellow),
+        "green" => Ok(ManualPalette::Green),
+        "dark-green" => Ok(ManualPalette::DarkGreen),
+        "inverted" => Ok(ManualPalette::Inverted),
+        _ => Err(format!("unknown palette {}", palette)),
+    }
+}
+
+fn save_game(gb: &GameBoy) {
+    if let Err(e) = gb.mmu.cartridge.save() {
+        eprintln!("Failed to write save file: {}", e);
+    }
+}
+
+fn queued_samples(audio_queue: &AudioQueue<f32>) -> u32 {
+    audio_queue.size() / (size_of::<f32>() * AUDIO_CHANNELS) as u32
+}
+
+/// Queue the samples generated by the last frame. When fast forwarding, only every
+/// `speedup`th sample is kept, which pitches the audio up instead of overflowing the queue.
+fn queue_frame_audio(gb: &mut GameBoy, audio_queue: &AudioQueue<f32>, speedup: usize) {
+    let samples: Vec<f32> = gb
+        .drain_audio_f32()
+        .chunks(AUDIO_CHANNELS)
+        .step_by(speedup)
+        .flatten()
+        .copied()
+        .collect();
+
+    if let Err(e) = audio_queue.queue_audio(&samples) {
+        eprintln!("Failed to queue audio: {}", e);
+    }
+}
+
 fn main() {
     let args = Args::parse();
     let mut speedup = 1;
 
-    let cartridge = match Cartridge::load_cartridge(&PathBuf::from(&args.cartridge_path)) {
+    let cartridge = match Cartridge::load_cartridge_with_patch(
+        &PathBuf::from(&args.cartridge_path),
+        args.patch.as_deref(),
+        Box::new(WallClock),
+    ) {
         Ok(cart) => cart,
         Err(e) => {
             eprintln!("Failed to load rom from {}: {}", args.cartridge_path, e);
             exit(1);
         }
     };
-    let mut gb = GameBoy::new(cartridge, args.print_serial);
+    let mut gb = match &args.boot_rom {
+        Some(boot_rom_path) => {
+            let boot_rom = fs::read(boot_rom_path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| BootRom::new(args.model, data).map_err(|e| e.to_string()));
+            match boot_rom {
+                Ok(boot_rom) => GameBoy::new_with_boot_rom(cartridge, args.print_serial, boot_rom),
+                Err(e) => {
+                    eprintln!(
+                        "Failed to load boot rom from {}: {}",
+                        boot_rom_path.display(),
+                        e
+                    );
+                    exit(1);
+                }
+            }
+        }
+        None => GameBoy::new_with_model(cartridge, args.print_serial, args.model),
+    };
+
+    if let Some(palette) = args.palette {
+        gb.set_compat_palette(palette.palette());
+    }
+
+    if let Some(camera_image) = &args.camera_image {
+        match StaticImage::from_png(camera_image) {
+            Ok(image) => gb.set_camera_image_source(Box::new(image)),
+            Err(e) => {
+                eprintln!(
+                    "Failed to load camera image from {}: {}",
+                    camera_image.display(),
+                    e
+                );
+                exit(1);
+            }
+        }
+    }
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+
+    // Fall back to pacing frames with sleep if there is no audio device
+    let audio_queue = sdl_context
+        .audio()
+        .and_then(|audio_subsystem| {
+            audio_subsystem.open_queue::<f32, _>(
+                None,
+                &AudioSpecDesired {
+                    freq: Some(SAMPLE_RATE),
+                    channels: Some(AUDIO_CHANNELS as u8),
+                    samples: Some(AUDIO_BUFFER_SIZE),
+                },
+            )
+        })
+        .inspect_err(|e| eprintln!("Failed to open audio device: {}", e))
+        .ok();
+
+    if let Some(audio_queue) = &audio_queue {
+        gb.set_sample_rate(audio_queue.spec().freq as u32);
+        audio_queue.resume();
+    }
+
+    // Super Game Boy games are shown with their border
+    let screen_size = if gb.sgb_pixel_data().is_some() {
+        (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT)
+    } else {
+        (GB_SCREEN_WIDTH, GB_SCREEN_HEIGHT)
+    };
+
     let window = video_subsystem
         .window(
             "Gameboy Emulator",
-            (GB_SCREEN_WIDTH * 4) as u32,
-            (GB_SCREEN_HEIGHT * 4) as u32,
+            (screen_size.0 * 4) as u32,
+            (screen_size.1 * 4) as u32,
         )
         .position_centered()
         .resizable()
@@ -83,7 +289,7 @@ fn main() {
 
     let mut canvas = window.into_canvas().build().unwrap();
     let (win_w, win_h) = canvas.window().size();
-    let mut screen_rect = get_screen_rect(win_w, win_h);
+    let mut screen_rect = get_screen_rect(win_w, win_h, screen_size);
     canvas.clear();
 
     let texture_creator = canvas.texture_creator();
@@ -91,13 +297,22 @@ fn main() {
         .create_texture(
             PixelFormatEnum::ARGB8888,
             TextureAccess::Streaming,
-            GB_SCREEN_WIDTH as u32,
-            GB_SCREEN_HEIGHT as u32,
+            screen_size.0 as u32,
+            screen_size.1 as u32,
         )
         .expect("Failed to create texture");
 
     let mut event_pump = sdl_context.event_pump().unwrap();
+
+    // the boot ROM reads the buttons itself when it runs
+    if args.palette.is_none()
+        && args.boot_rom.is_none()
+        && let Some(palette) = held_palette(&mut event_pump)
+    {
+        gb.set_compat_palette(palette.palette());
+    }
     let mut cycles_counter: Cycles = 0;
+    let mut last_save_time = Instant::now();
 
     'running: loop {
         let frame_start_time = Instant::now();
@@ -184,7 +399,7 @@ fn main() {
                     win_event: WindowEvent::Resized(w, h),
                     ..
                 } => {
-                    screen_rect = get_screen_rect(w as u32, h as u32);
+                    screen_rect = get_screen_rect(w as u32, h as u32, screen_size);
                 }
 
                 Event::KeyDown {
@@ -201,18 +416,49 @@ fn main() {
             }
         }
 
-        while cycles_counter < CYCLES_PER_FRAME as Cycles * speedup {
-            cycles_counter += gb.tick();
+        let (tilt_x, tilt_y) = read_tilt(&event_pump, screen_rect);
+        gb.set_tilt(tilt_x, tilt_y);
+
+        if last_save_time.elapsed() >= SAVE_INTERVAL {
+            save_game(&gb);
+            last_save_time = Instant::now();
         }
-        cycles_counter %= CYCLES_PER_FRAME as Cycles;
 
-        let elapsed = frame_start_time.elapsed();
-        if let Some(sleep_duration) = FRAME_DURATION.checked_sub(elapsed) {
-            thread::sleep(sleep_duration);
+        match &audio_queue {
+            Some(audio_queue) => {
+                // The audio device consumes samples at the real hardware rate,
+                // so only emulate another frame once the queue runs low
+                if queued_samples(audio_queue) >= AUDIO_QUEUE_TARGET {
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+
+                while cycles_counter < CYCLES_PER_FRAME as Cycles * speedup {
+                    cycles_counter += gb.tick();
+                }
+                cycles_counter %= CYCLES_PER_FRAME as Cycles;
+
+                queue_frame_audio(&mut gb, audio_queue, speedup);
+            }
+            None => {
+                while cycles_counter < CYCLES_PER_FRAME as Cycles * speedup {
+                    cycles_counter += gb.tick();
+                }
+                cycles_counter %= CYCLES_PER_FRAME as Cycles;
+
+                let elapsed = frame_start_time.elapsed();
+                if let Some(sleep_duration) = FRAME_DURATION.checked_sub(elapsed) {
+                    thread::sleep(sleep_duration);
+                }
+            }
         }
 
         texture
-            .update(None, gb.pixel_data(), GB_SCREEN_WIDTH * 4)
+            .update(
+                None,
+                gb.sgb_pixel_data().unwrap_or(gb.pixel_data()),
+                screen_size.0 * 4,
+            )
             .expect("Failed to update texture");
 
         canvas.clear();
@@ -221,4 +467,6 @@ fn main() {
             .expect("Failed to copy texture to canvas");
         canvas.present();
     }
+
+    save_game(&gb);
 }
@@ -0,0 +1,434 @@
+use crate::{
+    apu::{noise::NoiseChannel, pulse::PulseChannel, wave::WaveChannel},
+    cpu::{CPU_CLOCK_SPEED, Cycles},
+    utils::{is_set, set_bit},
+};
+
+mod envelope;
+mod length_counter;
+mod noise;
+mod pulse;
+mod wave;
+
+// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_PERIOD: usize = 8192;
+
+/// Samples are interleaved as left, right
+pub const AUDIO_CHANNELS: usize = 2;
+
+pub struct APU {
+    enabled: bool, // NR52 bit 7
+
+    nr50: u8, // master volume & VIN panning
+    nr51: u8, // sound panning
+
+    frame_sequencer_clock: usize,
+    frame_sequencer_step: u8,
+
+    ch1: PulseChannel,
+    ch2: PulseChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+
+    sample_rate: usize, // 0 while audio output is disabled
+    sample_clock: usize,
+    samples: Vec<f32>,
+    // high-pass filter capacitors for the left and right outputs
+    capacitors: [f32; AUDIO_CHANNELS],
+    capacitor_charge_factor: f32,
+}
+
+impl APU {
+    pub(crate) fn new() -> Self {
+        let mut apu = APU {
+            enabled: true,
+
+            nr50: 0x77,
+            nr51: 0xF3,
+
+            frame_sequencer_clock: 0,
+            frame_sequencer_step: 0,
+
+            ch1: PulseChannel::new(true),
+            ch2: PulseChannel::new(false),
+            ch3: WaveChannel::new(),
+            ch4: NoiseChannel::new(),
+
+            sample_rate: 0,
+            sample_clock: 0,
+            samples: Vec::new(),
+            capacitors: [0.0; AUDIO_CHANNELS],
+            capacitor_charge_factor: 0.0,
+        };
+
+        // state left behind by the boot ROM's chime
+        apu.ch1.write_byte(1, 0x80, false);
+        apu.ch1.write_byte(2, 0xF3, false);
+
+        apu
+    }
+
+    /// Start generating samples at the given rate, or stop if the rate is 0
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as usize;
+        self.sample_clock = 0;
+        self.samples.clear();
+        if sample_rate > 0 {
+            self.capacitor_charge_factor =
+                0.999958_f32.powf(CPU_CLOCK_SPEED as f32 / sample_rate as f32);
+        }
+    }
+
+    /// Take every sample generated since the last call, interleaved as left, right
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Whether the next frame sequencer step clocks the length counters
+    fn next_step_clocks_length(&self) -> bool {
+        self.frame_sequencer_step.is_multiple_of(2)
+    }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0xFF10..=0xFF14 => self.ch1.read_byte(address - 0xFF10),
+            // NR20 doesn't exist, but it reads as the missing sweep register
+            0xFF15..=0xFF19 => self.ch2.read_byte(address - 0xFF15),
+            0xFF1A..=0xFF1E => self.ch3.read_byte(address - 0xFF1A),
+            0xFF20..=0xFF23 => self.ch4.read_byte(address - 0xFF20),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                let mut nr52 = 0x70;
+                if self.enabled {
+                    nr52 = set_bit(nr52, 7);
+                }
+                if self.ch1.enabled() {
+                    nr52 = set_bit(nr52, 0);
+                }
+                if self.ch2.enabled() {
+                    nr52 = set_bit(nr52, 1);
+                }
+                if self.ch3.enabled() {
+                    nr52 = set_bit(nr52, 2);
+                }
+                if self.ch4.enabled() {
+                    nr52 = set_bit(nr52, 3);
+                }
+                nr52
+            }
+            0xFF30..=0xFF3F => self.ch3.read_wave_ram(address),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            0xFF26 => return self.set_power(is_set(byte, 7)),
+            // Wave RAM is unaffected by power
+            0xFF30..=0xFF3F => return self.ch3.write_wave_ram(address, byte),
+            _ => {}
+        }
+
+        // Registers are read only while powered off, with the exception of the length timers
+        if !self.enabled {
+            match address {
+                0xFF11 => self.ch1.write_length(byte),
+                0xFF16 => self.ch2.write_length(byte),
+                0xFF1B => self.ch3.write_length(byte),
+                0xFF20 => self.ch4.write_length(byte),
+                _ => {}
+            }
+            return;
+        }
+
+        let next_step_clocks_length = self.next_step_clocks_length();
+        match address {
+            0xFF10..=0xFF14 => self
+                .ch1
+                .write_byte(address - 0xFF10, byte, next_step_clocks_length),
+            0xFF16..=0xFF19 => self
+                .ch2
+                .write_byte(address - 0xFF15, byte, next_step_clocks_length),
+            0xFF1A..=0xFF1E => self
+                .ch3
+                .write_byte(address - 0xFF1A, byte, next_step_clocks_length),
+            0xFF20..=0xFF23 => self
+                .ch4
+                .write_byte(address - 0xFF20, byte, next_step_clocks_length),
+            0xFF24 => self.nr50 = byte,
+            0xFF25 => self.nr51 = byte,
+            _ => {}
+        }
+    }
+
+    fn set_power(&mut self, on: bool) {
+        if self.enabled && !on {
+            self.ch1.power_off();
+            self.ch2.power_off();
+            self.ch3.power_off();
+            self.ch4.power_off();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.enabled && on {
+            self.frame_sequencer_step = 0;
+            self.frame_sequencer_clock = 0;
+        }
+
+        self.enabled = on;
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => {
+                self.ch1.clock_length();
+                self.ch2.clock_length();
+                self.ch3.clock_length();
+                self.ch4.clock_length();
+            }
+            2 | 6 => {
+                self.ch1.clock_length();
+                self.ch2.clock_length();
+                self.ch3.clock_length();
+                self.ch4.clock_length();
+                self.ch1.clock_sweep();
+            }
+            7 => {
+                self.ch1.clock_envelope();
+                self.ch2.clock_envelope();
+                self.ch4.clock_envelope();
+            }
+            _ => {}
+        }
+
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    /// Mix the channels into a stereo sample according to NR50 and NR51
+    fn mix(&self) -> [f32; AUDIO_CHANNELS] {
+        // convert the digital output to an analog value between -1 and 1
+        let dac = |dac_enabled: bool, output: u8| {
+            if dac_enabled {
+                output as f32 / 7.5 - 1.0
+            } else {
+                0.0
+            }
+        };
+
+        let channels = [
+            dac(self.ch1.dac_enabled(), self.ch1.output()),
+            dac(self.ch2.dac_enabled(), self.ch2.output()),
+            dac(self.ch3.dac_enabled(), self.ch3.output()),
+            dac(self.ch4.dac_enabled(), self.ch4.output()),
+        ];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, channel) in channels.iter().enumerate() {
+            if is_set(self.nr51, i as u8 + 4) {
+                left += channel;
+            }
+            if is_set(self.nr51, i as u8) {
+                right += channel;
+            }
+        }
+
+        let left_volume = ((self.nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0x07) as f32 + 1.0;
+
+        [
+            left / 4.0 * left_volume / 8.0,
+            right / 4.0 * right_volume / 8.0,
+        ]
+    }
+
+    fn push_sample(&mut self) {
+        let sample = if self.enabled {
+            self.mix()
+        } else {
+            [0.0; AUDIO_CHANNELS]
+        };
+
+        // remove the DC offset like the capacitors on the real hardware
+        for (input, capacitor) in sample.into_iter().zip(self.capacitors.iter_mut()) {
+            let output = input - *capacitor;
+            *capacitor = input - output * self.capacitor_charge_factor;
+            self.samples.push(output);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: Cycles) {
+        if self.enabled {
+            self.frame_sequencer_clock += cycles;
+            while self.frame_sequencer_clock >= FRAME_SEQUENCER_PERIOD {
+                self.frame_sequencer_clock -= FRAME_SEQUENCER_PERIOD;
+                self.step_frame_sequencer();
+            }
+
+            self.ch1.tick(cycles);
+            self.ch2.tick(cycles);
+            self.ch3.tick(cycles);
+            self.ch4.tick(cycles);
+        }
+
+        if self.sample_rate == 0 {
+            return;
+        }
+
+        self.sample_clock += cycles * self.sample_rate;
+        while self.sample_clock >= CPU_CLOCK_SPEED {
+            self.sample_clock -= CPU_CLOCK_SPEED;
+            self.push_sample();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_register_read_masks() {
+        let mut apu = APU::new();
+        for address in 0xFF10..=0xFF25 {
+            apu.write_byte(address, 0x00);
+        }
+
+        assert_eq!(apu.read_byte(0xFF10), 0x80);
+        assert_eq!(apu.read_byte(0xFF11), 0x3F);
+        assert_eq!(apu.read_byte(0xFF12), 0x00);
+        assert_eq!(apu.read_byte(0xFF13), 0xFF);
+        assert_eq!(apu.read_byte(0xFF14), 0xBF);
+        assert_eq!(apu.read_byte(0xFF15), 0xFF);
+        assert_eq!(apu.read_byte(0xFF1A), 0x7F);
+        assert_eq!(apu.read_byte(0xFF1B), 0xFF);
+        assert_eq!(apu.read_byte(0xFF1C), 0x9F);
+        assert_eq!(apu.read_byte(0xFF1D), 0xFF);
+        assert_eq!(apu.read_byte(0xFF1E), 0xBF);
+        assert_eq!(apu.read_byte(0xFF1F), 0xFF);
+        assert_eq!(apu.read_byte(0xFF20), 0xFF);
+        assert_eq!(apu.read_byte(0xFF21), 0x00);
+        assert_eq!(apu.read_byte(0xFF22), 0x00);
+        assert_eq!(apu.read_byte(0xFF23), 0xBF);
+        assert_eq!(apu.read_byte(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn test_power_off_clears_registers() {
+        let mut apu = APU::new();
+        apu.write_byte(0xFF12, 0xF0);
+        apu.write_byte(0xFF14, 0x80);
+        assert_eq!(apu.read_byte(0xFF26), 0xF1);
+
+        apu.write_byte(0xFF26, 0x00);
+        assert_eq!(apu.read_byte(0xFF26), 0x70);
+        assert_eq!(apu.read_byte(0xFF12), 0x00);
+        assert_eq!(apu.read_byte(0xFF24), 0x00);
+
+        // writes are ignored while powered off
+        apu.write_byte(0xFF12, 0xF0);
+        assert_eq!(apu.read_byte(0xFF12), 0x00);
+    }
+
+    #[test]
+    fn test_length_counter_disables_channel() {
+        let mut apu = APU::new();
+        apu.write_byte(0xFF17, 0xF0);
+        apu.write_byte(0xFF16, 0x3E); // 2 length ticks
+        apu.write_byte(0xFF19, 0xC0);
+        assert!(is_set(apu.read_byte(0xFF26), 1));
+
+        apu.tick(FRAME_SEQUENCER_PERIOD);
+        assert!(is_set(apu.read_byte(0xFF26), 1));
+
+        apu.tick(FRAME_SEQUENCER_PERIOD * 2);
+        assert!(!is_set(apu.read_byte(0xFF26), 1));
+    }
+
+    #[test]
+    fn test_dac_disable_stops_channel() {
+        let mut apu = APU::new();
+        apu.write_byte(0xFF17, 0xF0);
+        apu.write_byte(0xFF19, 0x80);
+        assert!(is_set(apu.read_byte(0xFF26), 1));
+
+        apu.write_byte(0xFF17, 0x07);
+        assert!(!is_set(apu.read_byte(0xFF26), 1));
+    }
+
+    #[test]
+    fn test_wave_ram_access() {
+        let mut apu = APU::new();
+        for (i, address) in (0xFF30..=0xFF3F).enumerate() {
+            apu.write_byte(address, i as u8);
+        }
+        assert_eq!(apu.read_byte(0xFF35), 0x05);
+
+        // wave RAM survives power cycles
+        apu.write_byte(0xFF26, 0x00);
+        apu.write_byte(0xFF26, 0x80);
+        assert_eq!(apu.read_byte(0xFF35), 0x05);
+
+        // while playing, accesses are redirected to the byte being played
+        apu.write_byte(0xFF1A, 0x80);
+        apu.write_byte(0xFF1D, 0x00);
+        apu.write_byte(0xFF1E, 0x87); // period of (2048 - 0x700) * 2 cycles
+        apu.tick(0x100 * 2 * 5);
+        assert_eq!(apu.read_byte(0xFF30), 0x02);
+        assert!(is_set(apu.read_byte(0xFF26), 2));
+
+        apu.write_byte(0xFF1A, 0x00);
+        assert!(!is_set(apu.read_byte(0xFF26), 2));
+        assert_eq!(apu.read_byte(0xFF30), 0x00);
+    }
+
+    #[test]
+    fn test_sample_generation() {
+        let mut apu = APU::new();
+        apu.tick(CPU_CLOCK_SPEED / 60);
+        assert!(apu.drain_samples().is_empty());
+
+        apu.set_sample_rate(48000);
+        for _ in 0..CPU_CLOCK_SPEED / 16 {
+            apu.tick(16);
+        }
+        assert_eq!(apu.drain_samples().len(), 48000 * AUDIO_CHANNELS);
+        assert!(apu.drain_samples().is_empty());
+    }
+
+    #[test]
+    fn test_sample_panning() {
+        let mut apu = APU::new();
+        apu.set_sample_rate(48000);
+        apu.write_byte(0xFF25, 0x20); // channel 2 on the left only
+        apu.write_byte(0xFF17, 0xF0);
+        apu.write_byte(0xFF19, 0x80);
+        apu.tick(CPU_CLOCK_SPEED / 60);
+
+        let samples = apu.drain_samples();
+        assert!(
+            samples
+                .chunks(AUDIO_CHANNELS)
+                .any(|sample| sample[0] != 0.0)
+        );
+        assert!(
+            samples
+                .chunks(AUDIO_CHANNELS)
+                .all(|sample| sample[1] == 0.0)
+        );
+    }
+
+    #[test]
+    fn test_sweep_overflow_disables_channel() {
+        let mut apu = APU::new();
+        apu.write_byte(0xFF12, 0xF0);
+        apu.write_byte(0xFF10, 0x11); // period 1, increasing, shift 1
+        apu.write_byte(0xFF13, 0x00);
+        apu.write_byte(0xFF14, 0x85); // frequency 0x500
+        assert!(is_set(apu.read_byte(0xFF26), 0));
+
+        // the first sweep step sets 0x780, whose overflow check calculates 0x780 + 0x3C0 > 2047
+        apu.tick(FRAME_SEQUENCER_PERIOD * 3);
+        assert!(!is_set(apu.read_byte(0xFF26), 0));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::utils::is_set;
+
+pub struct Envelope {
+    register: u8, // NRx2
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, byte: u8) {
+        self.register = byte;
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// The DAC is powered whenever the upper 5 bits of NRx2 are not all zero
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    fn initial_volume(&self) -> u8 {
+        self.register >> 4
+    }
+
+    fn increasing(&self) -> bool {
+        is_set(self.register, 3)
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume();
+        self.timer = self.period();
+    }
+
+    /// Clocked by the frame sequencer at 64 Hz
+    pub fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period();
+
+            if self.increasing() && self.volume < 0x0F {
+                self.volume += 1;
+            } else if !self.increasing() && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
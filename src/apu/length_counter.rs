@@ -0,0 +1,61 @@
+pub struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Load the length timer from NRx1. The counter counts up from the written value
+    /// until it overflows at `max`, so store the remaining ticks instead.
+    pub fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    /// Clock the counter from the frame sequencer.
+    /// Returns true if the channel should be disabled.
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+
+        false
+    }
+
+    /// Handle a write to the length enable bit of NRx4.
+    ///
+    /// Enabling the counter while the next frame sequencer step won't clock it
+    /// results in an extra clock. Triggering with a zero counter reloads it.
+    /// Returns true if the channel should be disabled.
+    pub fn write_enable(&mut self, enable: bool, trigger: bool, next_step_clocks: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        let mut disable = false;
+        if !was_enabled && enable && !next_step_clocks && self.counter > 0 {
+            self.counter -= 1;
+            disable = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enable && !next_step_clocks {
+                self.counter -= 1;
+            }
+        }
+
+        disable
+    }
+}
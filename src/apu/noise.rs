@@ -0,0 +1,185 @@
+use crate::{
+    apu::{envelope::Envelope, length_counter::LengthCounter},
+    cpu::Cycles,
+    utils::is_set,
+};
+
+const DIVISORS: [usize; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+pub struct NoiseChannel {
+    enabled: bool,
+
+    clock_shift: u8,  // 4 bits
+    short_mode: bool, // 7 bit LFSR
+    divisor_code: u8, // 3 bits
+    timer: usize,
+    lfsr: u16, // 15 bits
+
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    /// Digital output of the channel, from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        // the output is the inverse of bit 0
+        (!self.lfsr & 0x01) as u8 * self.envelope.volume()
+    }
+
+    /// Reset every register on APU power off. Length counters are kept on the DMG.
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        *self = NoiseChannel::new();
+        self.length = length;
+        self.length.write_enable(false, false, true);
+    }
+
+    fn period(&self) -> usize {
+        DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    /// Read NR41 - NR44, where `register` is the offset from NR41
+    pub fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            0 => 0xFF,
+            1 => self.envelope.read(),
+            2 => (self.clock_shift << 4) | ((self.short_mode as u8) << 3) | self.divisor_code,
+            3 => 0xBF | ((self.length.enabled() as u8) << 6),
+            _ => panic!("Invalid noise channel register {}", register),
+        }
+    }
+
+    /// Write NR41 - NR44, where `register` is the offset from NR41
+    pub fn write_byte(&mut self, register: u16, byte: u8, next_step_clocks_length: bool) {
+        match register {
+            0 => self.length.load((byte & 0x3F) as u16),
+            1 => {
+                self.envelope.write(byte);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            2 => {
+                self.clock_shift = byte >> 4;
+                self.short_mode = is_set(byte, 3);
+                self.divisor_code = byte & 0x07;
+            }
+            3 => {
+                let trigger = is_set(byte, 7);
+                if self
+                    .length
+                    .write_enable(is_set(byte, 6), trigger, next_step_clocks_length)
+                {
+                    self.enabled = false;
+                }
+
+                if trigger {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.timer = self.period();
+                    self.lfsr = 0x7FFF;
+                    self.envelope.trigger();
+                }
+            }
+            _ => panic!("Invalid noise channel register {}", register),
+        }
+    }
+
+    /// Length can still be written while the APU is powered off
+    pub fn write_length(&mut self, byte: u8) {
+        self.length.load((byte & 0x3F) as u16);
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    fn clock_lfsr(&mut self) {
+        let feedback = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: Cycles) {
+        // clock shifts of 14 and 15 stop the LFSR from being clocked
+        if self.clock_shift >= 14 {
+            return;
+        }
+
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.clock_lfsr();
+        }
+        self.timer -= cycles;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lfsr_15_bit() {
+        let mut noise = NoiseChannel::new();
+        noise.clock_lfsr();
+        assert_eq!(noise.lfsr, 0x3FFF);
+
+        noise.lfsr = 0x0001;
+        noise.clock_lfsr();
+        assert_eq!(noise.lfsr, 0x4000);
+    }
+
+    #[test]
+    fn test_lfsr_7_bit() {
+        let mut noise = NoiseChannel::new();
+        noise.write_byte(2, 0x08, false);
+
+        noise.lfsr = 0x0001;
+        noise.clock_lfsr();
+        assert_eq!(noise.lfsr, 0x4040);
+
+        // the short mode repeats every 127 clocks
+        let start = noise.lfsr & 0x7F;
+        for _ in 0..127 {
+            noise.clock_lfsr();
+        }
+        assert_eq!(noise.lfsr & 0x7F, start);
+    }
+}
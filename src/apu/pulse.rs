@@ -0,0 +1,271 @@
+use crate::{
+    apu::{envelope::Envelope, length_counter::LengthCounter},
+    cpu::Cycles,
+    utils::is_set,
+};
+
+const DUTY_WAVEFORMS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+pub struct Sweep {
+    register: u8, // NR10
+    enabled: bool,
+    shadow_frequency: u16,
+    timer: u8,
+    // once a negate calculation has happened, clearing the negate bit disables the channel
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep {
+            register: 0,
+            enabled: false,
+            shadow_frequency: 0,
+            timer: 0,
+            negate_used: false,
+        }
+    }
+
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn negate(&self) -> bool {
+        is_set(self.register, 3)
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn reload_timer(&mut self) {
+        // a period of 0 is treated as 8
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    /// Calculate the next frequency. Returns None on overflow.
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow_frequency >> self.shift();
+        let frequency = if self.negate() {
+            self.negate_used = true;
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        };
+
+        if frequency > 2047 {
+            None
+        } else {
+            Some(frequency)
+        }
+    }
+
+    /// Returns false if the overflow check disabled the channel
+    fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow_frequency = frequency;
+        self.negate_used = false;
+        self.reload_timer();
+        self.enabled = self.period() != 0 || self.shift() != 0;
+
+        if self.shift() != 0 {
+            return self.calculate().is_some();
+        }
+
+        true
+    }
+
+    /// Returns false if the channel should be disabled
+    fn write(&mut self, byte: u8) -> bool {
+        let was_negate = self.negate();
+        self.register = byte;
+        !(was_negate && !self.negate() && self.negate_used)
+    }
+}
+
+pub struct PulseChannel {
+    enabled: bool,
+    sweep: Option<Sweep>, // only present on channel 1
+
+    duty: u8,
+    duty_step: usize,
+    timer: usize,
+    frequency: u16, // 11 bits
+
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl PulseChannel {
+    pub fn new(with_sweep: bool) -> Self {
+        PulseChannel {
+            enabled: false,
+            sweep: if with_sweep { Some(Sweep::new()) } else { None },
+
+            duty: 0,
+            duty_step: 0,
+            timer: 0,
+            frequency: 0,
+
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    /// Digital output of the channel, from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        DUTY_WAVEFORMS[self.duty as usize][self.duty_step] * self.envelope.volume()
+    }
+
+    /// Reset every register on APU power off. Length counters are kept on the DMG.
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        *self = PulseChannel::new(self.sweep.is_some());
+        self.length = length;
+        self.length.write_enable(false, false, true);
+    }
+
+    fn period(&self) -> usize {
+        (2048 - self.frequency as usize) * 4
+    }
+
+    /// Read NRx0 - NRx4, where `register` is the offset from NRx0
+    pub fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            0 => match &self.sweep {
+                Some(sweep) => 0x80 | sweep.register,
+                None => 0xFF,
+            },
+            1 => (self.duty << 6) | 0x3F,
+            2 => self.envelope.read(),
+            3 => 0xFF,
+            4 => 0xBF | ((self.length.enabled() as u8) << 6),
+            _ => panic!("Invalid pulse channel register {}", register),
+        }
+    }
+
+    /// Write NRx0 - NRx4, where `register` is the offset from NRx0
+    pub fn write_byte(&mut self, register: u16, byte: u8, next_step_clocks_length: bool) {
+        match register {
+            0 => {
+                if let Some(sweep) = &mut self.sweep
+                    && !sweep.write(byte)
+                {
+                    self.enabled = false;
+                }
+            }
+            1 => {
+                self.duty = byte >> 6;
+                self.length.load((byte & 0x3F) as u16);
+            }
+            2 => {
+                self.envelope.write(byte);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x0700) | byte as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((byte as u16 & 0x07) << 8);
+
+                let trigger = is_set(byte, 7);
+                if self
+                    .length
+                    .write_enable(is_set(byte, 6), trigger, next_step_clocks_length)
+                {
+                    self.enabled = false;
+                }
+
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => panic!("Invalid pulse channel register {}", register),
+        }
+    }
+
+    /// Length can still be written while the APU is powered off
+    pub fn write_length(&mut self, byte: u8) {
+        self.length.load((byte & 0x3F) as u16);
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if let Some(sweep) = &mut self.sweep
+            && !sweep.trigger(self.frequency)
+        {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer != 0 {
+            return;
+        }
+
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+
+        match sweep.calculate() {
+            Some(frequency) if sweep.shift() != 0 => {
+                sweep.shadow_frequency = frequency;
+                self.frequency = frequency;
+
+                // overflow check is run again with the new frequency
+                if sweep.calculate().is_none() {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => {}
+            None => self.enabled = false,
+        }
+    }
+
+    pub fn tick(&mut self, cycles: Cycles) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{apu::length_counter::LengthCounter, cpu::Cycles, utils::is_set};
+
+pub const WAVE_RAM_SIZE: usize = 16;
+
+// Wave RAM is uninitialized at power on, this is the pattern most DMGs end up with
+const INITIAL_WAVE_RAM: [u8; WAVE_RAM_SIZE] = [
+    0x84, 0x40, 0x43, 0xAA, 0x2D, 0x78, 0x92, 0x3C, 0x60, 0x59, 0x59, 0xB0, 0x34, 0xB8, 0x2E, 0xDA,
+];
+
+pub struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+
+    output_level: u8, // 2 bits
+    frequency: u16,   // 11 bits
+    timer: usize,
+    position: usize, // index of the current 4 bit sample
+
+    length: LengthCounter,
+    wave_ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+
+            output_level: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+
+            length: LengthCounter::new(256),
+            wave_ram: INITIAL_WAVE_RAM,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// Digital output of the channel, from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let byte = self.wave_ram[self.position / 2];
+        // the upper nibble is played first
+        let sample = if self.position.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+
+        match self.output_level {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            3 => sample >> 2,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Reset every register on APU power off. Length counters and wave RAM are kept on the DMG.
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(256));
+        let wave_ram = self.wave_ram;
+        *self = WaveChannel::new();
+        self.length = length;
+        self.length.write_enable(false, false, true);
+        self.wave_ram = wave_ram;
+    }
+
+    fn period(&self) -> usize {
+        (2048 - self.frequency as usize) * 2
+    }
+
+    /// Read NR30 - NR34, where `register` is the offset from NR30
+    pub fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            0 => 0x7F | ((self.dac_enabled as u8) << 7),
+            1 => 0xFF,
+            2 => 0x9F | (self.output_level << 5),
+            3 => 0xFF,
+            4 => 0xBF | ((self.length.enabled() as u8) << 6),
+            _ => panic!("Invalid wave channel register {}", register),
+        }
+    }
+
+    /// Write NR30 - NR34, where `register` is the offset from NR30
+    pub fn write_byte(&mut self, register: u16, byte: u8, next_step_clocks_length: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = is_set(byte, 7);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(byte as u16),
+            2 => self.output_level = (byte >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x0700) | byte as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((byte as u16 & 0x07) << 8);
+
+                let trigger = is_set(byte, 7);
+                if self
+                    .length
+                    .write_enable(is_set(byte, 6), trigger, next_step_clocks_length)
+                {
+                    self.enabled = false;
+                }
+
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            }
+            _ => panic!("Invalid wave channel register {}", register),
+        }
+    }
+
+    /// Length can still be written while the APU is powered off
+    pub fn write_length(&mut self, byte: u8) {
+        self.length.load(byte as u16);
+    }
+
+    /// While the channel is playing, wave RAM accesses go to the byte currently being played
+    fn wave_ram_index(&self, address: u16) -> usize {
+        if self.enabled {
+            self.position / 2
+        } else {
+            (address - 0xFF30) as usize
+        }
+    }
+
+    pub fn read_wave_ram(&self, address: u16) -> u8 {
+        self.wave_ram[self.wave_ram_index(address)]
+    }
+
+    pub fn write_wave_ram(&mut self, address: u16, byte: u8) {
+        let index = self.wave_ram_index(address);
+        self.wave_ram[index] = byte;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: Cycles) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % (WAVE_RAM_SIZE * 2);
+        }
+        self.timer -= cycles;
+    }
+}
@@ -0,0 +1,213 @@
+use std::{error::Error, fmt};
+
+use crate::{
+    cartridge::header::{CGBFlag, CartridgeHeader},
+    cpu::Registers,
+};
+
+pub mod compat_palettes;
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// Hardware revisions, which differ in the state the boot ROM leaves behind
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Model {
+    #[default]
+    DMG,
+    // Game Boy Pocket
+    MGB,
+    SGB,
+    CGB,
+}
+
+impl Model {
+    pub fn boot_rom_size(&self) -> usize {
+        match self {
+            Model::DMG | Model::MGB | Model::SGB => DMG_BOOT_ROM_SIZE,
+            Model::CGB => CGB_BOOT_ROM_SIZE,
+        }
+    }
+
+    /// DIV as the boot ROM leaves it, when skipping it. It depends on how long
+    /// each boot ROM runs, the SGB and CGB ones take longer than the DMG's.
+    pub(crate) fn post_boot_div(&self) -> u8 {
+        match self {
+            Model::DMG | Model::MGB => 0xAB,
+            Model::SGB => 0xD8,
+            Model::CGB => 0x1E,
+        }
+    }
+
+    /// CPU registers as the boot ROM leaves them, when skipping it
+    pub(crate) fn post_boot_registers(&self, header: &CartridgeHeader) -> Registers {
+        let mut registers = Registers::new();
+
+        let (af, bc, de, hl) = match self {
+            // H and C are only cleared when the header checksum is 0
+            Model::DMG | Model::MGB => {
+                let a = if *self == Model::MGB { 0xFF } else { 0x01 };
+                let f = if header.header_checksum.expected == 0 {
+                    0x80
+                } else {
+                    0xB0
+                };
+                (u16::from_be_bytes([a, f]), 0x0013, 0x00D8, 0x014D)
+            }
+            Model::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+            // games without CGB support are left in a compatibility mode
+            Model::CGB if header.cgb_flag == CGBFlag::DMGOnly => (0x1180, 0x0000, 0x0008, 0x007C),
+            Model::CGB => (0x1180, 0x0000, 0xFF56, 0x000D),
+        };
+
+        registers.set_af(af);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
+        registers.set_sp(0xFFFE);
+        registers.set_pc(0x0100);
+        registers
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BootRomError {
+    InvalidSize {
+        model: Model,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootRomError::InvalidSize {
+                model,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{:?} boot ROMs are {} bytes, but this one is {} bytes",
+                model, expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for BootRomError {}
+
+/// Boot ROM mapped over the cartridge until it is disabled through 0xFF50
+pub struct BootRom {
+    model: Model,
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn new(model: Model, data: Vec<u8>) -> Result<BootRom, BootRomError> {
+        if data.len() != model.boot_rom_size() {
+            return Err(BootRomError::InvalidSize {
+                model,
+                expected: model.boot_rom_size(),
+                actual: data.len(),
+            });
+        }
+        Ok(BootRom { model, data })
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// The byte at `address`, if the boot ROM covers it. The CGB boot ROM skips over
+    /// 0x0100 - 0x01FF, so the cartridge header can still be read.
+    pub fn read_byte(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0100..=0x01FF => None,
+            _ => self.data.get(address as usize).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::header::HEADER_END;
+    #[cfg(not(feature = "test"))]
+    use crate::{
+        cartridge::{Cartridge, ROM_BANK_SIZE},
+        mmu::MMU,
+    };
+
+    #[test]
+    fn test_boot_rom_mapping() {
+        assert!(BootRom::new(Model::CGB, vec![0; DMG_BOOT_ROM_SIZE]).is_err());
+
+        let boot_rom = BootRom::new(Model::DMG, vec![0x31; DMG_BOOT_ROM_SIZE]).unwrap();
+        assert_eq!(boot_rom.read_byte(0x00FF), Some(0x31));
+        assert_eq!(boot_rom.read_byte(0x0100), None);
+
+        let boot_rom = BootRom::new(Model::CGB, vec![0x31; CGB_BOOT_ROM_SIZE]).unwrap();
+        assert_eq!(boot_rom.read_byte(0x0150), None);
+        assert_eq!(boot_rom.read_byte(0x0200), Some(0x31));
+        assert_eq!(boot_rom.read_byte(0x08FF), Some(0x31));
+        assert_eq!(boot_rom.read_byte(0x0900), None);
+    }
+
+    // the test feature replaces the memory map with flat RAM
+    #[cfg(not(feature = "test"))]
+    #[test]
+    fn test_boot_rom_disable() {
+        let mut rom = vec![0xAA; 2 * ROM_BANK_SIZE];
+        // ROM only cartridge, no RAM
+        rom[0x0143..HEADER_END].fill(0);
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        let boot_rom = BootRom::new(Model::DMG, vec![0x31; DMG_BOOT_ROM_SIZE]).unwrap();
+        let mut mmu = MMU::new_with_boot_rom(cartridge, false, boot_rom);
+        assert_eq!(mmu.read_byte(0x0000), 0x31);
+        assert_eq!(mmu.read_byte(0x0100), 0xAA);
+
+        // writing 0 leaves the boot ROM mapped
+        mmu.write_byte(0xFF50, 0x00);
+        assert_eq!(mmu.read_byte(0x0000), 0x31);
+        mmu.write_byte(0xFF50, 0x01);
+        assert_eq!(mmu.read_byte(0x0000), 0xAA);
+    }
+
+    #[test]
+    fn test_post_boot_registers() {
+        let mut rom = vec![0; HEADER_END];
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        let registers = Model::DMG.post_boot_registers(&header);
+        assert_eq!(registers.af(), 0x0180);
+
+        rom[0x014D] = 0x42;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(Model::DMG.post_boot_registers(&header).af(), 0x01B0);
+        assert_eq!(Model::MGB.post_boot_registers(&header).af(), 0xFFB0);
+        assert_eq!(Model::CGB.post_boot_registers(&header).de(), 0x0008);
+
+        rom[0x0143] = 0x80;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        let registers = Model::CGB.post_boot_registers(&header);
+        assert_eq!(registers.af(), 0x1180);
+        assert_eq!(registers.de(), 0xFF56);
+        assert_eq!(registers.pc(), 0x0100);
+    }
+
+    #[cfg(not(feature = "test"))]
+    #[test]
+    fn test_post_boot_io() {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[0x0143..HEADER_END].fill(0);
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        let mmu = MMU::new_with_model(cartridge, false, Model::DMG);
+        assert_eq!(mmu.read_byte(0xFF04), 0xAB);
+        assert_eq!(mmu.read_byte(0xFF0F), 0xE1);
+        assert_eq!(mmu.read_byte(0xFF40), 0x91);
+
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        let mmu = MMU::new_with_model(cartridge, false, Model::CGB);
+        assert_eq!(mmu.read_byte(0xFF04), 0x1E);
+    }
+}
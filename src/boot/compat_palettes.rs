@@ -0,0 +1,369 @@
+use crate::{
+    cartridge::header::CartridgeHeader,
+    gb::{JoypadButton, JoypadDpad},
+};
+
+/// Colors the CGB boot ROM gives DMG games, in RGB555
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompatPalette {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+// The boot ROM's palettes, in RGB555. Combinations below index into these by color,
+// so a few of them start part way into a palette.
+const PALETTES: [[u16; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
+
+/// Four colors of `PALETTES`, starting at color `index`
+const fn colors(index: usize) -> [u16; 4] {
+    let mut colors = [0; 4];
+    let mut i = 0;
+    while i < 4 {
+        colors[i] = PALETTES[(index + i) / 4][(index + i) % 4];
+        i += 1;
+    }
+    colors
+}
+
+/// A combination of `PALETTES` by palette number
+const fn combination(obj0: usize, obj1: usize, bg: usize) -> CompatPalette {
+    combination_at(obj0 * 4, obj1 * 4, bg * 4)
+}
+
+/// A combination of `PALETTES` by color index
+const fn combination_at(obj0: usize, obj1: usize, bg: usize) -> CompatPalette {
+    CompatPalette {
+        bg: colors(bg),
+        obj0: colors(obj0),
+        obj1: colors(obj1),
+    }
+}
+
+const COMBINATIONS: [CompatPalette; 51] = [
+    combination(4, 4, 29),
+    combination(18, 18, 18),
+    combination(20, 20, 20),
+    combination(24, 24, 24),
+    combination(9, 9, 9),
+    combination(0, 0, 0),
+    combination(27, 27, 27),
+    combination(5, 5, 5),
+    combination(12, 12, 12),
+    combination(26, 26, 26),
+    combination(16, 8, 8),
+    combination(4, 28, 28),
+    combination(4, 2, 2),
+    combination(3, 4, 4),
+    combination(4, 29, 29),
+    combination(28, 4, 28),
+    combination(2, 17, 2),
+    combination(16, 16, 8),
+    combination(4, 4, 7),
+    combination(4, 4, 18),
+    combination(4, 4, 20),
+    combination(19, 19, 9),
+    combination_at(4 * 4 - 1, 4 * 4 - 1, 11 * 4),
+    combination(17, 17, 2),
+    combination(4, 4, 2),
+    combination(4, 4, 3),
+    combination(28, 28, 0),
+    combination(3, 3, 0),
+    combination(0, 0, 1),
+    combination(18, 22, 18),
+    combination(20, 22, 20),
+    combination(24, 22, 24),
+    combination(16, 22, 8),
+    combination(17, 4, 13),
+    combination_at(28 * 4 - 1, 0, 14 * 4),
+    combination_at(28 * 4 - 1, 4 * 4, 15 * 4),
+    combination(19, 22, 9),
+    combination(16, 28, 10),
+    combination(4, 23, 28),
+    combination(17, 22, 2),
+    combination(4, 0, 2),
+    combination(4, 28, 3),
+    combination(28, 3, 0),
+    combination(3, 28, 4),
+    combination(21, 28, 4),
+    combination(3, 28, 0),
+    combination(25, 3, 28),
+    combination(0, 28, 8),
+    combination(4, 3, 28),
+    combination(28, 3, 6),
+    combination(4, 28, 29),
+];
+
+/// Palettes the player can pick by holding a direction, and optionally A or B,
+/// while the CGB boot logo is shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManualPalette {
+    Brown,
+    Red,
+    DarkBrown,
+    Blue,
+    DarkBlue,
+    Grayscale,
+    Pastel,
+    Orange,
+    Yellow,
+    Green,
+    // the default for games the boot ROM doesn't recognize
+    DarkGreen,
+    Inverted,
+}
+
+impl ManualPalette {
+    pub fn from_buttons(dpad: JoypadDpad, button: Option<JoypadButton>) -> Option<ManualPalette> {
+        match (dpad, button) {
+            (JoypadDpad::Up, None) => Some(ManualPalette::Brown),
+            (JoypadDpad::Up, Some(JoypadButton::A)) => Some(ManualPalette::Red),
+            (JoypadDpad::Up, Some(JoypadButton::B)) => Some(ManualPalette::DarkBrown),
+            (JoypadDpad::Left, None) => Some(ManualPalette::Blue),
+            (JoypadDpad::Left, Some(JoypadButton::A)) => Some(ManualPalette::DarkBlue),
+            (JoypadDpad::Left, Some(JoypadButton::B)) => Some(ManualPalette::Grayscale),
+            (JoypadDpad::Down, None) => Some(ManualPalette::Pastel),
+            (JoypadDpad::Down, Some(JoypadButton::A)) => Some(ManualPalette::Orange),
+            (JoypadDpad::Down, Some(JoypadButton::B)) => Some(ManualPalette::Yellow),
+            (JoypadDpad::Right, None) => Some(ManualPalette::Green),
+            (JoypadDpad::Right, Some(JoypadButton::A)) => Some(ManualPalette::DarkGreen),
+            (JoypadDpad::Right, Some(JoypadButton::B)) => Some(ManualPalette::Inverted),
+            // Start and Select don't pick a palette
+            _ => None,
+        }
+    }
+
+    pub fn palette(&self) -> CompatPalette {
+        let combination = match self {
+            ManualPalette::Brown => 5,
+            ManualPalette::Red => 43,
+            ManualPalette::DarkBrown => 28,
+            ManualPalette::Blue => 48,
+            ManualPalette::DarkBlue => 40,
+            ManualPalette::Grayscale => 7,
+            ManualPalette::Pastel => 8,
+            ManualPalette::Orange => 3,
+            ManualPalette::Yellow => 49,
+            ManualPalette::Green => 1,
+            ManualPalette::DarkGreen => 0,
+            ManualPalette::Inverted => 6,
+        };
+        COMBINATIONS[combination]
+    }
+}
+
+// Title checksums of the Nintendo games the boot ROM recognizes, and the combination
+// each one gets. Checksums from FIRST_DUPLICATE on are shared by several games, which
+// are told apart by the fourth letter of their title.
+const TITLE_CHECKSUMS: [(u8, usize); 94] = [
+    (0x00, 0),  // default
+    (0x88, 4),  // ALLEY WAY
+    (0x16, 5),  // YAKUMAN
+    (0x36, 35), // BASEBALL
+    (0xD1, 34), // TENNIS
+    (0xDB, 3),  // TETRIS
+    (0xF2, 31), // QIX
+    (0x3C, 15), // DR.MARIO
+    (0x8C, 10), // RADARMISSION
+    (0x92, 5),  // F1RACE
+    (0x3D, 19), // YOSSY NO TAMAGO
+    (0x5C, 36),
+    (0x58, 7),  // X
+    (0xC9, 37), // MARIOLAND2
+    (0x3E, 30), // YOSSY NO COOKIE
+    (0x70, 44), // ZELDA
+    (0x1D, 21),
+    (0x59, 32),
+    (0x69, 31), // TETRIS FLASH
+    (0x19, 20), // DONKEY KONG
+    (0x35, 5),  // MARIO'S PICROSS
+    (0xA8, 33),
+    (0x14, 13), // POKEMON RED
+    (0xAA, 14), // POKEMON GREEN
+    (0x75, 5),  // PICROSS 2
+    (0x95, 29), // YOSSY NO PANEPON
+    (0x99, 5),  // KIRAKIRA KIDS
+    (0x34, 18), // GAMEBOY GALLERY
+    (0x6F, 9),  // POCKETCAMERA
+    (0x15, 3),
+    (0xFF, 2),  // BALLOON KID
+    (0x97, 26), // KINGOFTHEZOO
+    (0x4B, 25), // DMG FOOTBALL
+    (0x90, 25), // WORLD CUP
+    (0x17, 41), // OTHELLO
+    (0x10, 42), // SUPER RC PRO-AM
+    (0x39, 26), // DYNABLASTER
+    (0xF7, 45), // BOY AND BLOB GB2
+    (0xF6, 42), // MEGAMAN
+    (0xA2, 45), // STAR WARS-NOA
+    (0x49, 36),
+    (0x4E, 38), // WAVERACE
+    (0x43, 26),
+    (0x68, 42), // LOLO2
+    (0xE0, 30), // YOSHI'S COOKIE
+    (0x8B, 41), // MYSTIC QUEST
+    (0xF0, 34),
+    (0xCE, 34), // TOPRANKINGTENNIS
+    (0x0C, 5),  // MANSELL
+    (0x29, 42), // MEGAMAN3
+    (0xE8, 6),  // SPACE INVADERS
+    (0xB7, 5),  // GAME&WATCH
+    (0x86, 33), // DONKEYKONGLAND95
+    (0x9A, 25), // ASTEROIDS/MISCMD
+    (0x52, 42), // STREET FIGHTER 2
+    (0x01, 42), // DEFENDER/JOUST
+    (0x9D, 40), // KILLERINSTINCT95
+    (0x71, 2),  // TETRIS BLAST
+    (0x9C, 16), // PINOCCHIO
+    (0xBD, 25),
+    (0x5D, 42), // BA.TOSHINDEN
+    (0x6D, 42), // NETTOU KOF 95
+    (0x67, 5),
+    (0x3F, 0),  // TETRIS PLUS
+    (0x6B, 39), // DONKEYKONGLAND 3
+    (0xB3, 36),
+    (0x46, 22), // SUPER MARIOLAND
+    (0x28, 25), // GOLF
+    (0xA5, 6),  // SOLARSTRIKER
+    (0xC6, 32), // GBWARS
+    (0xD3, 12), // KAERUNOTAMENI
+    (0x27, 36),
+    (0x61, 11), // POKEMON BLUE
+    (0x18, 39), // DONKEYKONGLAND
+    (0x66, 18), // GAMEBOY GALLERY2
+    (0x6A, 39), // DONKEYKONGLAND 2
+    (0xBF, 24), // KID ICARUS
+    (0x0D, 31), // TETRIS2
+    (0xF4, 50),
+    (0xB3, 17), // MOGURANYA
+    (0x46, 46),
+    (0x28, 6),  // GALAGA&GALAXIAN
+    (0xA5, 27), // BT2RAGNAROKWORLD
+    (0xC6, 0),  // KEN GRIFFEY JR
+    (0xD3, 47),
+    (0x27, 41), // MAGNETIC SOCCER
+    (0x61, 41), // VEGAS STAKES
+    (0x18, 0),
+    (0x66, 0),  // MILLI/CENTI/PEDE
+    (0x6A, 19), // MARIO & YOSHI
+    (0xBF, 34), // SOCCER
+    (0x0D, 23), // POKEBOM
+    (0xF4, 18), // G&W GALLERY
+    (0xB3, 29), // TETRIS ATTACK
+];
+
+const FIRST_DUPLICATE: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+const NINTENDO_LICENSEE_CODE: u8 = 0x01;
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
+
+/// The palette the CGB boot ROM picks for a DMG game. Only games published by
+/// Nintendo are looked up by title, everything else gets the default.
+pub fn header_palette(header: &CartridgeHeader) -> CompatPalette {
+    let nintendo = match header.old_licensee_code {
+        USE_NEW_LICENSEE_CODE => header.new_licensee_code.as_deref() == Some("01"),
+        code => code == NINTENDO_LICENSEE_CODE,
+    };
+    if !nintendo {
+        return COMBINATIONS[0];
+    }
+
+    let fourth_letter = header.title.as_bytes().get(3).copied();
+    TITLE_CHECKSUMS
+        .iter()
+        .enumerate()
+        .find(|(i, (checksum, _))| {
+            *checksum == header.title_checksum
+                && (*i < FIRST_DUPLICATE
+                    || Some(FOURTH_LETTERS[i - FIRST_DUPLICATE]) == fourth_letter)
+        })
+        .map(|(_, (_, combination))| COMBINATIONS[*combination])
+        .unwrap_or(COMBINATIONS[0])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::header::HEADER_END;
+
+    const RED: [u16; 4] = [0x7FFF, 0x421F, 0x1CF2, 0x0000];
+    const GREEN: [u16; 4] = [0x7FFF, 0x1BEF, 0x0200, 0x0000];
+    const BLUE: [u16; 4] = [0x7FFF, 0x7E8C, 0x7C00, 0x0000];
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(colors(4 * 4), RED);
+        // combinations can start in the middle of a palette
+        assert_eq!(colors(4 * 4 - 1), [0x0000, 0x7FFF, 0x421F, 0x1CF2]);
+        assert_eq!(ManualPalette::Grayscale.palette().bg[1], 0x5294);
+    }
+
+    #[test]
+    fn test_header_palette() {
+        let mut rom = vec![0; HEADER_END];
+        rom[0x0134..0x0134 + 11].copy_from_slice(b"POKEMON RED");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header_palette(&header), ManualPalette::DarkGreen.palette());
+
+        rom[0x014B] = NINTENDO_LICENSEE_CODE;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header_palette(&header).obj0, GREEN);
+
+        rom[0x014B] = USE_NEW_LICENSEE_CODE;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header_palette(&header).bg, RED);
+    }
+
+    #[test]
+    fn test_duplicate_checksums() {
+        let mut rom = vec![0; HEADER_END];
+        rom[0x014B] = NINTENDO_LICENSEE_CODE;
+
+        // both sum to 0x61, the fourth letter tells them apart
+        rom[0x0134..0x0134 + 12].copy_from_slice(b"POKEMON BLUE");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header_palette(&header).bg, BLUE);
+
+        rom[0x0134..0x0134 + 12].copy_from_slice(b"VEGAS STAKES");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header_palette(&header).bg, GREEN);
+
+        // a known checksum with an unknown fourth letter gets the default
+        rom[0x0134..0x0134 + 12].copy_from_slice(b"VEGBS STAKER");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header_palette(&header), ManualPalette::DarkGreen.palette());
+    }
+}
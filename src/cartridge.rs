@@ -1,71 +1,336 @@
 use std::{
-    fs::File,
+    error::Error,
+    fmt,
+    fs::{self, File},
     io::{self, Read},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
+use crate::cartridge::camera::Camera;
+use crate::cartridge::clock::{Clock, WallClock};
+use crate::cartridge::header::CartridgeHeader;
+use crate::cartridge::huc1::HuC1;
+use crate::cartridge::huc3::HuC3;
+use crate::cartridge::image_source::ImageSource;
 use crate::cartridge::mbc1::MBC1;
+use crate::cartridge::mbc2::MBC2;
 use crate::cartridge::mbc3::MBC3;
+use crate::cartridge::mbc5::MBC5;
+use crate::cartridge::mbc7::MBC7;
+use crate::cartridge::patch::{PATCH_EXTENSIONS, PatchError, apply_patch};
+use crate::cpu::Cycles;
 
+#[cfg(feature = "archive")]
+mod archive;
+mod camera;
+pub mod clock;
+pub mod header;
+mod huc1;
+mod huc3;
+pub mod image_source;
 mod mbc1;
+mod mbc2;
 mod mbc3;
+mod mbc5;
+mod mbc7;
+pub mod patch;
 
 pub const ROM_BANK_SIZE: usize = 0x4000;
 pub const RAM_BANK_SIZE: usize = 0x2000;
 
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+pub const LOGO_ADDRESS: usize = 0x0104;
+
+// Each game in an MBC1M multicart occupies 16 banks
+const MULTICART_GAME_SIZE: usize = 0x40000;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    // smaller than the cartridge header
+    TooShort { size: usize },
+    InvalidHeader { field: &'static str, value: u8 },
+    UnsupportedMapper(u8),
+    SizeMismatch { declared: usize, actual: usize },
+    // a zip archive without a .gb or .gbc file in it
+    NoRomInArchive,
+    Patch(PatchError),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "{}", e),
+            CartridgeError::TooShort { size } => {
+                write!(f, "ROM is too small to contain a header: {} bytes", size)
+            }
+            CartridgeError::InvalidHeader { field, value } => {
+                write!(f, "Invalid {} in header: {:#04X}", field, value)
+            }
+            CartridgeError::UnsupportedMapper(cart_type) => {
+                write!(f, "Unsupported cartridge type: {:#04X}", cart_type)
+            }
+            CartridgeError::SizeMismatch { declared, actual } => write!(
+                f,
+                "ROM is {} bytes, but its header declares {} bytes",
+                actual, declared
+            ),
+            CartridgeError::NoRomInArchive => write!(f, "No .gb or .gbc file found in archive"),
+            CartridgeError::Patch(e) => write!(f, "Failed to apply patch: {}", e),
+        }
+    }
+}
+
+impl Error for CartridgeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CartridgeError::Io(e) => Some(e),
+            CartridgeError::Patch(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+impl From<PatchError> for CartridgeError {
+    fn from(e: PatchError) -> Self {
+        CartridgeError::Patch(e)
+    }
+}
+
 pub struct Cartridge {
-    pub title: String,
+    pub header: CartridgeHeader,
+    pub has_battery: bool,
+    // where battery backed RAM is persisted, next to the ROM
+    pub save_path: Option<PathBuf>,
 
     pub mbc: Box<dyn MBC>,
 }
 
 impl Cartridge {
-    pub fn load_cartridge(path: &Path) -> io::Result<Cartridge> {
+    pub fn load_cartridge(path: &Path) -> Result<Cartridge, CartridgeError> {
+        Cartridge::load_cartridge_with_clock(path, Box::new(WallClock))
+    }
+
+    /// Load a cartridge whose real time clock, if it has one, is driven by `clock`.
+    /// Battery backed RAM is restored from, and saved to, a `.sav` file next to the ROM.
+    pub fn load_cartridge_with_clock(
+        path: &Path,
+        clock: Box<dyn Clock>,
+    ) -> Result<Cartridge, CartridgeError> {
+        Cartridge::load_cartridge_with_patch(path, None, clock)
+    }
+
+    /// Load a cartridge with an IPS, UPS or BPS patch applied. Without `patch_path`,
+    /// a patch with the same name as the ROM is used if there is one.
+    pub fn load_cartridge_with_patch(
+        path: &Path,
+        patch_path: Option<&Path>,
+        clock: Box<dyn Clock>,
+    ) -> Result<Cartridge, CartridgeError> {
         let mut f = File::open(path)?;
         let mut rom = Vec::new();
 
         f.read_to_end(&mut rom)?;
 
-        let title = String::from_utf8_lossy(&rom[0x0134..0x0143]).to_string();
-
-        // if rom[0x143] == 0xC0 {
-        //     panic!("CGB cartridge not supported");
-        // }
-
-        let cart_type = rom[0x147];
-        // let rom_size = ROM_BANK_SIZE * 2 * (1 << rom[0x148]);
-        let ram_size = RAM_BANK_SIZE
-            * match rom[0x149] {
-                0x00 => 0,
-                0x01 => unreachable!("Unused RAM size"),
-                0x02 => 1,
-                0x03 => 4,
-                0x04 => 16,
-                0x05 => 64,
-                _ => unreachable!("Invalid RAM size"),
-            };
+        #[cfg(feature = "archive")]
+        let rom = archive::extract_rom(rom)?;
+
+        let patch_path = patch_path.map(Path::to_path_buf).or_else(|| {
+            PATCH_EXTENSIONS
+                .iter()
+                .map(|extension| path.with_extension(extension))
+                .find(|patch_path| patch_path.exists())
+        });
+        let rom = match patch_path {
+            Some(patch_path) => apply_patch(&rom, &fs::read(patch_path)?)?,
+            None => rom,
+        };
+
+        let mut cartridge = Cartridge::from_bytes_with_clock(rom, clock)?;
+        if cartridge.has_battery {
+            let save_path = path.with_extension("sav");
+            if save_path.exists() {
+                let save = fs::read(&save_path)?;
+                // the RTC state, if any, is appended after the RAM
+                let ram_size = cartridge.mbc.export_ram().len().min(save.len());
+                cartridge.mbc.import_ram(&save[..ram_size]);
+                cartridge.mbc.import_rtc(&save[ram_size..]);
+            }
+            cartridge.save_path = Some(save_path);
+        }
+
+        Ok(cartridge)
+    }
+
+    /// Create a cartridge from the contents of a ROM. It has no save file,
+    /// but battery backed RAM can still be exported through the MBC.
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_bytes_with_clock(rom, Box::new(WallClock))
+    }
+
+    pub fn from_bytes_with_clock(
+        rom: Vec<u8>,
+        clock: Box<dyn Clock>,
+    ) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        if rom.len() != header.rom_size {
+            return Err(CartridgeError::SizeMismatch {
+                declared: header.rom_size,
+                actual: rom.len(),
+            });
+        }
+
+        let cart_type = header.cartridge_type;
+        let ram_size = header.ram_size;
 
         let mbc: Box<dyn MBC> = match cart_type {
-            0x00 => {
+            0x00 | 0x08 | 0x09 => {
                 let mut mbc = NoMBC::new();
                 mbc.load_rom(rom.as_slice());
                 Box::new(mbc)
             }
+            0x01..=0x03 if Cartridge::is_multicart(&rom) => {
+                Box::new(MBC1::new_multicart(rom, ram_size))
+            }
             0x01..=0x03 => Box::new(MBC1::new(rom, ram_size)),
-            0x0F..=0x13 => Box::new(MBC3::new(rom, ram_size)),
-            _ => panic!("Unsupported cartridge type: {:#04X}", cart_type),
+            0x05 | 0x06 => Box::new(MBC2::new(rom)),
+            0x0F..=0x13 => Box::new(MBC3::new(
+                rom,
+                ram_size,
+                matches!(cart_type, 0x0F | 0x10),
+                clock,
+            )),
+            0x19..=0x1E => Box::new(MBC5::new(rom, ram_size, matches!(cart_type, 0x1C..=0x1E))),
+            0x22 => Box::new(MBC7::new(rom)),
+            0xFC => Box::new(Camera::new(rom, ram_size)),
+            0xFE => Box::new(HuC3::new(rom, ram_size, clock)),
+            0xFF => Box::new(HuC1::new(rom, ram_size)),
+            _ => return Err(CartridgeError::UnsupportedMapper(cart_type)),
         };
 
         #[cfg(not(feature = "gb_doctor"))]
-        println!("Loaded ROM: {}", title);
+        println!("Loaded ROM: {}", header.title);
+
+        Ok(Cartridge {
+            header,
+            has_battery: Cartridge::has_battery(cart_type),
+            save_path: None,
+            mbc,
+        })
+    }
+
+    /// MBC1M multicarts are 1 MiB ROMs with the header of another game after the menu,
+    /// so look for a repeated Nintendo logo at the start of each 256 KiB game.
+    fn is_multicart(rom: &[u8]) -> bool {
+        if rom.len() != 4 * MULTICART_GAME_SIZE {
+            return false;
+        }
+
+        (1..4).any(|game| {
+            let logo_address = game * MULTICART_GAME_SIZE + LOGO_ADDRESS;
+            rom[logo_address..logo_address + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+        })
+    }
+
+    fn has_battery(cart_type: u8) -> bool {
+        matches!(
+            cart_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFC..=0xFF
+        )
+    }
+
+    /// Write battery backed RAM to the save file
+    pub fn save(&self) -> io::Result<()> {
+        match &self.save_path {
+            Some(save_path) if self.has_battery => {
+                let mut save = self.mbc.export_ram();
+                if let Some(rtc) = self.mbc.export_rtc() {
+                    save.extend(rtc);
+                }
+                fs::write(save_path, save)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Read from a ROM bank. Banks past the end of the ROM mirror the ones before,
+/// since the upper bank bits aren't connected on smaller ROMs.
+fn read_rom(rom: &[u8], bank: usize, address: u16) -> u8 {
+    if rom.is_empty() {
+        return 0xFF;
+    }
+    let offset = address as usize % ROM_BANK_SIZE;
+    rom[(bank * ROM_BANK_SIZE + offset) % rom.len()]
+}
 
-        Ok(Cartridge { title, mbc })
+/// Offset of `address` into banked external RAM, mirrored the same way as ROM banks.
+/// There is nothing to access on cartridges without RAM.
+fn ram_address(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
     }
+    let bank_count = (ram.len() / RAM_BANK_SIZE).max(1);
+    Some(((bank % bank_count) * RAM_BANK_SIZE + (address - 0xA000) as usize) % ram.len())
+}
+
+/// Restore external RAM from a save, which may be shorter or longer than the RAM
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = data.len().min(ram.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+/// ROM where every byte holds the number of its bank, to tell which bank is mapped
+#[cfg(test)]
+fn banked_rom(banks: usize) -> Vec<u8> {
+    (0..banks * ROM_BANK_SIZE)
+        .map(|i| (i / ROM_BANK_SIZE) as u8)
+        .collect()
 }
 
 pub trait MBC {
     fn read_byte(&self, address: u16) -> u8;
     fn write_byte(&mut self, address: u16, byte: u8);
+
+    /// Called with the cycles of every emulator tick
+    fn tick(&mut self, _cycles: Cycles) {}
+
+    /// Contents of the external RAM, used to persist battery backed saves
+    fn export_ram(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the external RAM from a save
+    fn import_ram(&mut self, _data: &[u8]) {}
+
+    /// Real time clock state to append to the save, for mappers that have one
+    fn export_rtc(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restore the real time clock from the end of a save
+    fn import_rtc(&mut self, _data: &[u8]) {}
+
+    /// Whether the rumble motor is currently on, for cartridges that have one
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Tilt the cartridge for mappers with an accelerometer, in g along each axis
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Where the Pocket Camera's sensor gets its pictures from
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
 }
 
 pub struct NoMBC {
@@ -81,8 +346,14 @@ impl NoMBC {
         }
     }
 
+    /// ROMs smaller than 32 KiB are mirrored, anything past 32 KiB can't be reached
     fn load_rom(&mut self, data: &[u8]) {
-        self.rom.copy_from_slice(data);
+        if data.is_empty() {
+            return;
+        }
+        for (i, byte) in self.rom.iter_mut().enumerate() {
+            *byte = data[i % data.len()];
+        }
     }
 }
 
@@ -106,4 +377,99 @@ impl MBC for NoMBC {
             _ => panic!("Illegal address for MBC"),
         }
     }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_multicart_detection() {
+        let mut rom = vec![0; 4 * MULTICART_GAME_SIZE];
+        rom[LOGO_ADDRESS..LOGO_ADDRESS + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        assert!(!Cartridge::is_multicart(&rom));
+
+        let logo_address = MULTICART_GAME_SIZE + LOGO_ADDRESS;
+        rom[logo_address..logo_address + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        assert!(Cartridge::is_multicart(&rom));
+
+        // regular MBC1 games of other sizes are never multicarts
+        rom.truncate(2 * MULTICART_GAME_SIZE);
+        assert!(!Cartridge::is_multicart(&rom));
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert_eq!(cartridge.header.title, "TEST");
+        assert!(cartridge.has_battery);
+        assert_eq!(cartridge.save_path, None);
+        assert_eq!(cartridge.mbc.export_ram().len(), RAM_BANK_SIZE);
+    }
+
+    fn load_rom(name: &str, rom: &[u8]) -> Result<Cartridge, CartridgeError> {
+        let path = std::env::temp_dir().join(format!("gb-emulator-{}.gb", name));
+        fs::write(&path, rom).unwrap();
+        let cartridge = Cartridge::load_cartridge(&path);
+        fs::remove_file(&path).unwrap();
+        cartridge
+    }
+
+    #[test]
+    fn test_patch_next_to_rom() {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[0x0147] = 0x20;
+        let rom_path = std::env::temp_dir().join("gb-emulator-patched.gb");
+        fs::write(&rom_path, &rom).unwrap();
+
+        // patch the cartridge type back to ROM only
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x01, 0x47, 0x00, 0x01, 0x00]);
+        patch.extend_from_slice(b"EOF");
+        let patch_path = rom_path.with_extension("ips");
+        fs::write(&patch_path, &patch).unwrap();
+
+        let cartridge = Cartridge::load_cartridge(&rom_path);
+        fs::remove_file(&rom_path).unwrap();
+        fs::remove_file(&patch_path).unwrap();
+        assert_eq!(cartridge.unwrap().header.cartridge_type, 0x00);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(matches!(
+            load_rom("too-short", &[0; 0x100]),
+            Err(CartridgeError::TooShort { size: 0x100 })
+        ));
+
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[0x0147] = 0x20;
+        assert!(matches!(
+            load_rom("unsupported", &rom),
+            Err(CartridgeError::UnsupportedMapper(0x20))
+        ));
+
+        rom[0x0147] = 0x00;
+        rom.truncate(ROM_BANK_SIZE);
+        assert!(matches!(
+            load_rom("truncated", &rom),
+            Err(CartridgeError::SizeMismatch {
+                declared: 0x8000,
+                actual: 0x4000
+            })
+        ));
+    }
 }
@@ -0,0 +1,89 @@
+use std::io::{self, Cursor, Read};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::cartridge::CartridgeError;
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+const ROM_EXTENSIONS: [&str; 2] = [".gb", ".gbc"];
+
+/// Decompress zip and gzip archives, detected by their magic bytes.
+/// Anything else is assumed to already be a ROM and returned as it is.
+pub fn extract_rom(data: Vec<u8>) -> Result<Vec<u8>, CartridgeError> {
+    if data.starts_with(&ZIP_MAGIC) {
+        extract_zip(data)
+    } else if data.starts_with(&GZIP_MAGIC) {
+        let mut rom = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut rom)?;
+        Ok(rom)
+    } else {
+        Ok(data)
+    }
+}
+
+/// Extract the first .gb or .gbc file in the archive
+fn extract_zip(data: Vec<u8>) -> Result<Vec<u8>, CartridgeError> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(io::Error::other)?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(io::Error::other)?;
+        let name = file.name().to_ascii_lowercase();
+        if file.is_file() && ROM_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) {
+            let mut rom = Vec::new();
+            file.read_to_end(&mut rom)?;
+            return Ok(rom);
+        }
+    }
+
+    Err(CartridgeError::NoRomInArchive)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::{Compression, write::GzEncoder};
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let archive = zip(&[
+            ("readme.txt", b"hello"),
+            ("Game.GBC", b"rom"),
+            ("other.gb", b"no"),
+        ]);
+        assert_eq!(extract_rom(archive).unwrap(), b"rom");
+
+        let archive = zip(&[("readme.txt", b"hello")]);
+        assert!(matches!(
+            extract_rom(archive),
+            Err(CartridgeError::NoRomInArchive)
+        ));
+    }
+
+    #[test]
+    fn test_extract_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"rom").unwrap();
+        assert_eq!(extract_rom(encoder.finish().unwrap()).unwrap(), b"rom");
+
+        // plain ROMs pass straight through
+        assert_eq!(extract_rom(b"rom".to_vec()).unwrap(), b"rom");
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{
+    cartridge::{
+        self, MBC,
+        image_source::{CAMERA_HEIGHT, CAMERA_WIDTH, ImageSource, TestPattern},
+        load_ram, read_rom,
+    },
+    cpu::Cycles,
+    utils::is_set,
+};
+
+// Writing this RAM bank maps the camera registers at 0xA000 instead of RAM
+const CAMERA_REGISTERS_BANK: u8 = 0x10;
+const CAMERA_REGISTER_COUNT: usize = 0x36;
+
+// Register 0
+const CAPTURE_BIT: u8 = 0;
+// Register 1
+const N_BIT: u8 = 7;
+// Register 4
+const INVERT_BIT: u8 = 3;
+
+// Start of the 4x4 dither matrix, 3 thresholds per pixel
+const DITHER_MATRIX: usize = 0x06;
+
+// The captured picture is stored as tiles in RAM bank 0
+const IMAGE_ADDRESS: usize = 0x0100;
+
+// Edge enhancement ratios selected by register 4 bits 4-6
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+pub struct Camera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    ram_enable: bool,
+    rom_bank_number: u8, // 6 bits
+    ram_bank_number: u8, // 4 bits, or 0x10 for the camera registers
+
+    registers: [u8; CAMERA_REGISTER_COUNT],
+    capture_cycles: Cycles, // until the capture in progress finishes
+    image_source: Box<dyn ImageSource>,
+}
+
+impl Camera {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Camera {
+            rom,
+            ram: vec![0; ram_size],
+
+            ram_enable: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+
+            registers: [0; CAMERA_REGISTER_COUNT],
+            capture_cycles: 0,
+            image_source: Box::new(TestPattern),
+        }
+    }
+
+    fn registers_mapped(&self) -> bool {
+        is_set(self.ram_bank_number, 4)
+    }
+
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        cartridge::ram_address(&self.ram, self.ram_bank_number as usize, address)
+    }
+
+    fn exposure(&self) -> u16 {
+        u16::from_be_bytes([self.registers[2], self.registers[3]])
+    }
+
+    /// How long a capture takes, in CPU cycles. Mostly the exposure time,
+    /// plus a fixed overhead that is shorter when the N bit is set.
+    fn capture_duration(&self) -> Cycles {
+        let overhead = if is_set(self.registers[1], N_BIT) {
+            0
+        } else {
+            2048
+        };
+        129792 + overhead + self.exposure() as Cycles * 64
+    }
+
+    /// Run the sensor's picture through the exposure, edge enhancement, inversion
+    /// and dithering steps, and store it as tiles in RAM
+    fn capture(&mut self) {
+        let mut pixels = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        self.image_source.capture(&mut pixels);
+
+        // exposure 0x1000 leaves the picture as it is
+        let exposed: Vec<f32> = pixels
+            .iter()
+            .map(|&pixel| pixel as f32 * self.exposure() as f32 / 0x1000 as f32)
+            .collect();
+
+        let edge_ratio = EDGE_RATIOS[((self.registers[4] >> 4) & 0x07) as usize];
+        let edge_mode = (self.registers[1] >> 5) & 0x03;
+        let invert = is_set(self.registers[4], INVERT_BIT);
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let pixel = |dx: isize, dy: isize| {
+                    let x = (x as isize + dx).clamp(0, CAMERA_WIDTH as isize - 1) as usize;
+                    let y = (y as isize + dy).clamp(0, CAMERA_HEIGHT as isize - 1) as usize;
+                    exposed[y * CAMERA_WIDTH + x]
+                };
+
+                // compare against the horizontal neighbours, the vertical ones, or both
+                let neighbours = match edge_mode {
+                    0b01 => vec![pixel(-1, 0), pixel(1, 0)],
+                    0b10 => vec![pixel(0, -1), pixel(0, 1)],
+                    0b11 => vec![pixel(-1, 0), pixel(1, 0), pixel(0, -1), pixel(0, 1)],
+                    _ => vec![],
+                };
+                let mut value = pixel(0, 0);
+                if !neighbours.is_empty() {
+                    let average = neighbours.iter().sum::<f32>() / neighbours.len() as f32;
+                    value += edge_ratio * (value - average);
+                }
+
+                let mut value = value.clamp(0.0, 255.0) as u8;
+                if invert {
+                    value = !value;
+                }
+
+                self.write_pixel(x, y, self.dither(x, y, value));
+            }
+        }
+    }
+
+    /// Pick a shade using the thresholds for this pixel's position in the dither matrix
+    fn dither(&self, x: usize, y: usize, value: u8) -> u8 {
+        let matrix = DITHER_MATRIX + ((y % 4) * 4 + x % 4) * 3;
+        let thresholds = &self.registers[matrix..matrix + 3];
+
+        // brighter than every threshold is white, which is shade 0
+        3 - thresholds
+            .iter()
+            .filter(|&&threshold| value >= threshold)
+            .count() as u8
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, shade: u8) {
+        let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+        let address = IMAGE_ADDRESS + tile * 16 + (y % 8) * 2;
+        if address + 1 >= self.ram.len() {
+            return;
+        }
+
+        let bit = 7 - (x % 8);
+        let mask = !(1 << bit);
+        self.ram[address] = (self.ram[address] & mask) | ((shade & 0x01) << bit);
+        self.ram[address + 1] = (self.ram[address + 1] & mask) | (((shade >> 1) & 0x01) << bit);
+    }
+}
+
+impl MBC for Camera {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_rom(&self.rom, self.rom_bank_number as usize, address),
+            // only register 0 can be read back, the rest are write only
+            0xA000..=0xBFFF if self.registers_mapped() => {
+                if address & 0x7F == 0 {
+                    self.registers[0]
+                } else {
+                    0x00
+                }
+            }
+            // RAM can be read without being enabled
+            0xA000..=0xBFFF => match self.ram_address(address) {
+                Some(address) => self.ram[address],
+                None => 0xFF,
+            },
+            _ => panic!("Invalid Camera address: {:#06X}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enable = (byte & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank_number = byte & 0x3F,
+            0x4000..=0x5FFF => {
+                self.ram_bank_number = if is_set(byte, 4) {
+                    CAMERA_REGISTERS_BANK
+                } else {
+                    byte & 0x0F
+                }
+            }
+            0x6000..=0x7FFF => {}
+            // the registers are mirrored every 0x80 bytes
+            0xA000..=0xBFFF if self.registers_mapped() => {
+                let register = (address & 0x7F) as usize;
+                match register {
+                    0 => {
+                        // a capture can be cancelled, but not restarted while running
+                        let busy = is_set(self.registers[0], CAPTURE_BIT);
+                        self.registers[0] = byte & 0x07;
+                        if is_set(byte, CAPTURE_BIT) && !busy {
+                            self.capture_cycles = self.capture_duration();
+                        } else if !is_set(byte, CAPTURE_BIT) {
+                            self.capture_cycles = 0;
+                        }
+                    }
+                    1..CAMERA_REGISTER_COUNT => self.registers[register] = byte,
+                    _ => {}
+                }
+            }
+            0xA000..=0xBFFF => {
+                if self.ram_enable
+                    && let Some(address) = self.ram_address(address)
+                {
+                    self.ram[address] = byte;
+                }
+            }
+            _ => panic!("Invalid Camera address: {:#06X}", address),
+        }
+    }
+
+    fn tick(&mut self, cycles: Cycles) {
+        if self.capture_cycles == 0 {
+            return;
+        }
+
+        self.capture_cycles = self.capture_cycles.saturating_sub(cycles);
+        if self.capture_cycles == 0 {
+            self.capture();
+            self.registers[0] &= !(1 << CAPTURE_BIT);
+        }
+    }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.image_source = source;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    fn camera() -> Camera {
+        let mut mbc = Camera::new(vec![0; 2 * ROM_BANK_SIZE], 16 * RAM_BANK_SIZE);
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, CAMERA_REGISTERS_BANK);
+        // unity exposure
+        mbc.write_byte(0xA002, 0x10);
+        mbc.write_byte(0xA003, 0x00);
+        // thresholds at 0x40, 0x80 and 0xC0 everywhere
+        for pixel in 0..16 {
+            for (i, threshold) in [0x40, 0x80, 0xC0].into_iter().enumerate() {
+                mbc.write_byte(0xA000 + (DITHER_MATRIX + pixel * 3 + i) as u16, threshold);
+            }
+        }
+        mbc
+    }
+
+    fn take_picture(mbc: &mut Camera) {
+        mbc.write_byte(0xA000, 0x01);
+        assert_eq!(mbc.read_byte(0xA000) & 0x01, 0x01);
+        while mbc.read_byte(0xA000) & 0x01 != 0 {
+            mbc.tick(1024);
+        }
+        mbc.write_byte(0x4000, 0x00);
+    }
+
+    #[test]
+    fn test_register_bank() {
+        let mut mbc = camera();
+        assert_eq!(mbc.read_byte(0xA001), 0x00);
+        // registers are mirrored every 0x80 bytes
+        mbc.write_byte(0xA080, 0x04);
+        assert_eq!(mbc.read_byte(0xA000), 0x04);
+
+        mbc.write_byte(0x4000, 0x01);
+        mbc.write_byte(0xA000, 0x12);
+        assert_eq!(mbc.ram[RAM_BANK_SIZE], 0x12);
+    }
+
+    #[test]
+    fn test_capture_dithering() {
+        let mut mbc = camera();
+        mbc.set_image_source(Box::new(|pixels: &mut [u8]| {
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = [0x00, 0x50, 0x90, 0xFF][(i % CAMERA_WIDTH) / 2 % 4];
+            }
+        }));
+        take_picture(&mut mbc);
+
+        // the first row of the first tile reads black, dark grey, light grey, white
+        assert_eq!(mbc.read_byte(0xA100), 0b1100_1100);
+        assert_eq!(mbc.read_byte(0xA101), 0b1111_0000);
+    }
+
+    #[test]
+    fn test_capture_invert() {
+        let mut mbc = camera();
+        mbc.write_byte(0xA004, 1 << INVERT_BIT);
+        mbc.set_image_source(Box::new(|pixels: &mut [u8]| pixels.fill(0xFF)));
+        take_picture(&mut mbc);
+
+        assert_eq!(mbc.read_byte(0xA100), 0xFF);
+        assert_eq!(mbc.read_byte(0xA101), 0xFF);
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    cell::Cell,
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::cpu::{CPU_CLOCK_SPEED, Cycles};
+
+/// Time source for cartridge real time clocks
+pub trait Clock {
+    /// Current time, as a duration since the UNIX epoch
+    fn now(&self) -> Duration;
+
+    /// Called with the cycles of every emulator tick
+    fn tick(&mut self, _cycles: Cycles) {}
+}
+
+/// Follows the host's system time
+pub struct WallClock;
+
+impl Clock for WallClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Advances with emulated time, so fast forwarding or replaying inputs moves the clock
+/// at the same rate as the game
+pub struct CycleClock {
+    start: Duration,
+    cycles: u128,
+}
+
+impl CycleClock {
+    pub fn new(start: Duration) -> Self {
+        CycleClock { start, cycles: 0 }
+    }
+}
+
+impl Clock for CycleClock {
+    fn now(&self) -> Duration {
+        let nanos = self.cycles * 1_000_000_000 / CPU_CLOCK_SPEED as u128;
+        self.start + Duration::from_nanos(nanos as u64)
+    }
+
+    fn tick(&mut self, cycles: Cycles) {
+        self.cycles += cycles as u128;
+    }
+}
+
+/// Only moves when told to. Clones share the same time, so a handle can be kept
+/// to control the clock after it is given to a cartridge.
+#[derive(Clone)]
+pub struct FixedClock {
+    time: Rc<Cell<Duration>>,
+}
+
+impl FixedClock {
+    pub fn new(time: Duration) -> Self {
+        FixedClock {
+            time: Rc::new(Cell::new(time)),
+        }
+    }
+
+    pub fn set(&self, time: Duration) {
+        self.time.set(time);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.time.set(self.time.get() + duration);
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> Duration {
+        self.time.get()
+    }
+}
@@ -0,0 +1,259 @@
+use crate::cartridge::{CartridgeError, LOGO_ADDRESS, NINTENDO_LOGO, ROM_BANK_SIZE};
+
+pub const HEADER_END: usize = 0x0150;
+
+const TITLE_ADDRESS: usize = 0x0134;
+const MANUFACTURER_CODE_ADDRESS: usize = 0x013F;
+const CGB_FLAG_ADDRESS: usize = 0x0143;
+const NEW_LICENSEE_CODE_ADDRESS: usize = 0x0144;
+const SGB_FLAG_ADDRESS: usize = 0x0146;
+const CARTRIDGE_TYPE_ADDRESS: usize = 0x0147;
+const ROM_SIZE_ADDRESS: usize = 0x0148;
+const RAM_SIZE_ADDRESS: usize = 0x0149;
+const DESTINATION_ADDRESS: usize = 0x014A;
+const OLD_LICENSEE_CODE_ADDRESS: usize = 0x014B;
+const MASK_ROM_VERSION_ADDRESS: usize = 0x014C;
+const HEADER_CHECKSUM_ADDRESS: usize = 0x014D;
+const GLOBAL_CHECKSUM_ADDRESS: usize = 0x014E;
+
+// Old licensee code meaning the new licensee code should be used instead
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CGBFlag {
+    DMGOnly,
+    // works on both, with extra CGB features
+    CGBEnhanced,
+    CGBOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+/// A checksum stored in the header, along with the one computed from the ROM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checksum<T> {
+    pub expected: T,
+    pub computed: T,
+}
+
+impl<T: PartialEq> Checksum<T> {
+    pub fn verified(&self) -> bool {
+        self.expected == self.computed
+    }
+}
+
+/// Cartridge information stored at 0x0100 - 0x014F of the ROM
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    // sum of the 16 title bytes, which the CGB boot ROM picks DMG game palettes by
+    pub title_checksum: u8,
+    // only present in later CGB cartridges
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: CGBFlag,
+    pub sgb_flag: bool,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub old_licensee_code: u8,
+    // only used when the old licensee code is 0x33
+    pub new_licensee_code: Option<String>,
+    pub mask_rom_version: u8,
+    pub header_checksum: Checksum<u8>,
+    pub global_checksum: Checksum<u16>,
+    pub has_valid_logo: bool,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::TooShort { size: rom.len() });
+        }
+
+        let cgb_flag = match rom[CGB_FLAG_ADDRESS] {
+            0xC0 => CGBFlag::CGBOnly,
+            flag if flag & 0x80 != 0 => CGBFlag::CGBEnhanced,
+            _ => CGBFlag::DMGOnly,
+        };
+
+        // The title used to be 16 bytes long, then the CGB flag took its last byte
+        // and newer cartridges also took 4 more for the manufacturer code
+        let manufacturer_code = &rom[MANUFACTURER_CODE_ADDRESS..CGB_FLAG_ADDRESS];
+        let (title_end, manufacturer_code) = match cgb_flag {
+            CGBFlag::DMGOnly => (NEW_LICENSEE_CODE_ADDRESS, None),
+            _ if CartridgeHeader::is_manufacturer_code(manufacturer_code) => (
+                MANUFACTURER_CODE_ADDRESS,
+                Some(String::from_utf8_lossy(manufacturer_code).to_string()),
+            ),
+            _ => (CGB_FLAG_ADDRESS, None),
+        };
+        let title = String::from_utf8_lossy(&rom[TITLE_ADDRESS..title_end])
+            .trim_end_matches('\0')
+            .to_string();
+
+        let old_licensee_code = rom[OLD_LICENSEE_CODE_ADDRESS];
+        let new_licensee_code = (old_licensee_code == USE_NEW_LICENSEE_CODE).then(|| {
+            String::from_utf8_lossy(&rom[NEW_LICENSEE_CODE_ADDRESS..SGB_FLAG_ADDRESS]).to_string()
+        });
+
+        Ok(CartridgeHeader {
+            title,
+            title_checksum: rom[TITLE_ADDRESS..NEW_LICENSEE_CODE_ADDRESS]
+                .iter()
+                .fold(0u8, |checksum, byte| checksum.wrapping_add(*byte)),
+            manufacturer_code,
+            cgb_flag,
+            sgb_flag: rom[SGB_FLAG_ADDRESS] == 0x03,
+            cartridge_type: rom[CARTRIDGE_TYPE_ADDRESS],
+            rom_size: CartridgeHeader::rom_size(rom[ROM_SIZE_ADDRESS])?,
+            ram_size: CartridgeHeader::ram_size(rom[RAM_SIZE_ADDRESS])?,
+            destination: match rom[DESTINATION_ADDRESS] {
+                0x00 => Destination::Japan,
+                _ => Destination::Overseas,
+            },
+            old_licensee_code,
+            new_licensee_code,
+            mask_rom_version: rom[MASK_ROM_VERSION_ADDRESS],
+            header_checksum: Checksum {
+                expected: rom[HEADER_CHECKSUM_ADDRESS],
+                computed: CartridgeHeader::header_checksum(rom),
+            },
+            global_checksum: Checksum {
+                expected: u16::from_be_bytes([
+                    rom[GLOBAL_CHECKSUM_ADDRESS],
+                    rom[GLOBAL_CHECKSUM_ADDRESS + 1],
+                ]),
+                computed: CartridgeHeader::global_checksum(rom),
+            },
+            has_valid_logo: rom[LOGO_ADDRESS..LOGO_ADDRESS + NINTENDO_LOGO.len()] == NINTENDO_LOGO,
+        })
+    }
+
+    /// Manufacturer codes are 4 uppercase letters or digits
+    fn is_manufacturer_code(bytes: &[u8]) -> bool {
+        bytes
+            .iter()
+            .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit())
+    }
+
+    fn rom_size(code: u8) -> Result<usize, CartridgeError> {
+        match code {
+            0x00..=0x08 => Ok((2 * ROM_BANK_SIZE) << code),
+            // only listed in unofficial docs, no known cartridges use them
+            0x52 => Ok(72 * ROM_BANK_SIZE),
+            0x53 => Ok(80 * ROM_BANK_SIZE),
+            0x54 => Ok(96 * ROM_BANK_SIZE),
+            _ => Err(CartridgeError::InvalidHeader {
+                field: "ROM size",
+                value: code,
+            }),
+        }
+    }
+
+    fn ram_size(code: u8) -> Result<usize, CartridgeError> {
+        match code {
+            0x00 => Ok(0),
+            // unused by any licensed cartridge, but some homebrew declares 2 KiB
+            0x01 => Ok(0x800),
+            0x02 => Ok(0x2000),
+            0x03 => Ok(0x8000),
+            0x04 => Ok(0x20000),
+            0x05 => Ok(0x10000),
+            _ => Err(CartridgeError::InvalidHeader {
+                field: "RAM size",
+                value: code,
+            }),
+        }
+    }
+
+    /// Checked by the boot ROM, which locks up if it doesn't match
+    fn header_checksum(rom: &[u8]) -> u8 {
+        rom[TITLE_ADDRESS..HEADER_CHECKSUM_ADDRESS]
+            .iter()
+            .fold(0u8, |checksum, byte| {
+                checksum.wrapping_sub(*byte).wrapping_sub(1)
+            })
+    }
+
+    /// Sum of every byte in the ROM apart from the checksum itself. Nothing checks it.
+    fn global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|(i, _)| !(GLOBAL_CHECKSUM_ADDRESS..GLOBAL_CHECKSUM_ADDRESS + 2).contains(i))
+            .fold(0u16, |checksum, (_, byte)| {
+                checksum.wrapping_add(*byte as u16)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom_with_header(title: &[u8], cgb_flag: u8) -> Vec<u8> {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[LOGO_ADDRESS..LOGO_ADDRESS + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom[CGB_FLAG_ADDRESS] = cgb_flag;
+        rom[TITLE_ADDRESS..TITLE_ADDRESS + title.len()].copy_from_slice(title);
+        rom[HEADER_CHECKSUM_ADDRESS] = CartridgeHeader::header_checksum(&rom);
+        let global_checksum = CartridgeHeader::global_checksum(&rom).to_be_bytes();
+        rom[GLOBAL_CHECKSUM_ADDRESS..GLOBAL_CHECKSUM_ADDRESS + 2].copy_from_slice(&global_checksum);
+        rom
+    }
+
+    #[test]
+    fn test_title_and_manufacturer_code() {
+        let header = CartridgeHeader::parse(&rom_with_header(b"POKEMON BLUE", 0x00)).unwrap();
+        assert_eq!(header.title, "POKEMON BLUE");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_flag, CGBFlag::DMGOnly);
+
+        let header = CartridgeHeader::parse(&rom_with_header(b"POKEMON_SLVAAXE", 0x80)).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb_flag, CGBFlag::CGBEnhanced);
+
+        // older titles run into the CGB flag's address
+        let header = CartridgeHeader::parse(&rom_with_header(b"SUPER MARIO LAND", 0x00)).unwrap();
+        assert_eq!(header.title, "SUPER MARIO LAND");
+    }
+
+    #[test]
+    fn test_checksums_and_logo() {
+        let mut rom = rom_with_header(b"TEST", 0x00);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum.verified());
+        assert!(header.global_checksum.verified());
+        assert!(header.has_valid_logo);
+        assert_eq!(header.rom_size, rom.len());
+
+        rom[TITLE_ADDRESS] = b'X';
+        rom[LOGO_ADDRESS] = 0x00;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum.verified());
+        assert!(!header.global_checksum.verified());
+        assert!(!header.has_valid_logo);
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        let mut rom = rom_with_header(b"TEST", 0x00);
+        rom[RAM_SIZE_ADDRESS] = 0x06;
+        assert!(matches!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::InvalidHeader {
+                field: "RAM size",
+                value: 0x06
+            })
+        ));
+        assert!(matches!(
+            CartridgeHeader::parse(&rom[..0x100]),
+            Err(CartridgeError::TooShort { size: 0x100 })
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::cartridge::{self, MBC, load_ram, read_rom};
+
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    ir_mode: bool,       // 0xA000 - 0xBFFF maps the IR port instead of RAM
+    rom_bank_number: u8, // 6 bits
+    ram_bank_number: u8, // 2 bits
+}
+
+impl HuC1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        HuC1 {
+            rom,
+            ram: vec![0; ram_size],
+
+            ir_mode: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+        }
+    }
+
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        cartridge::ram_address(&self.ram, self.ram_bank_number as usize, address)
+    }
+}
+
+impl MBC for HuC1 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_rom(&self.rom, self.rom_bank_number as usize, address),
+            // bit 0 is set while IR light is received, there is never another device
+            0xA000..=0xBFFF if self.ir_mode => 0xC0,
+            0xA000..=0xBFFF => match self.ram_address(address) {
+                Some(address) => self.ram[address],
+                None => 0xFF,
+            },
+            _ => panic!("Invalid HuC1 address: {:#06X}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ir_mode = (byte & 0x0F) == 0x0E,
+            0x2000..=0x3FFF => self.rom_bank_number = (byte & 0x3F).max(1),
+            0x4000..=0x5FFF => self.ram_bank_number = byte & 0x03,
+            0x6000..=0x7FFF => {}
+            // bit 0 drives the IR LED, which nothing is listening to
+            0xA000..=0xBFFF if self.ir_mode => {}
+            0xA000..=0xBFFF => {
+                if let Some(address) = self.ram_address(address) {
+                    self.ram[address] = byte;
+                }
+            }
+            _ => panic!("Invalid HuC1 address: {:#06X}", address),
+        }
+    }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, banked_rom};
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = HuC1::new(banked_rom(64), 0);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+
+        mbc.write_byte(0x2000, 0x3F);
+        assert_eq!(mbc.read_byte(0x4000), 0x3F);
+        assert_eq!(mbc.read_byte(0x0000), 0);
+
+        // bank 0 maps to bank 1
+        mbc.write_byte(0x2000, 0x00);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+    }
+
+    #[test]
+    fn test_ir_mode() {
+        let mut mbc = HuC1::new(banked_rom(2), 4 * RAM_BANK_SIZE);
+        mbc.write_byte(0x4000, 0x02);
+        mbc.write_byte(0xA000, 0x12);
+        assert_eq!(mbc.read_byte(0xA000), 0x12);
+
+        mbc.write_byte(0x0000, 0x0E);
+        assert_eq!(mbc.read_byte(0xA000), 0xC0);
+        // writes go to the IR LED instead of RAM
+        mbc.write_byte(0xA000, 0x01);
+
+        // any other value maps RAM back in
+        mbc.write_byte(0x0000, 0x00);
+        assert_eq!(mbc.read_byte(0xA000), 0x12);
+        mbc.write_byte(0x4000, 0x00);
+        assert_eq!(mbc.read_byte(0xA000), 0x00);
+    }
+}
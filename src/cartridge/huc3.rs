@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use crate::{
+    cartridge::{self, MBC, clock::Clock, load_ram, read_rom},
+    cpu::Cycles,
+};
+
+const MINUTES_PER_DAY: u64 = 60 * 24;
+
+// Same layout SameBoy uses: timestamp, minutes, days, alarm minutes, alarm days, alarm enable
+const RTC_FOOTER_SIZE: usize = 17;
+
+// Locations of the clock and alarm in the RTC's nibble addressed memory
+const TIME_ADDRESS: usize = 0x00;
+const ALARM_ADDRESS: usize = 0x58;
+
+#[derive(Clone, Copy, PartialEq)]
+enum HuC3Mode {
+    RAMReadOnly = 0x0,
+    RAMReadWrite = 0xA,
+    RTCCommand = 0xB,
+    RTCResponse = 0xC,
+    RTCSemaphore = 0xD,
+    IR = 0xE,
+    Unknown,
+}
+
+impl HuC3Mode {
+    fn from_u8(byte: u8) -> Self {
+        match byte & 0x0F {
+            0x0 => HuC3Mode::RAMReadOnly,
+            0xA => HuC3Mode::RAMReadWrite,
+            0xB => HuC3Mode::RTCCommand,
+            0xC => HuC3Mode::RTCResponse,
+            0xD => HuC3Mode::RTCSemaphore,
+            0xE => HuC3Mode::IR,
+            _ => HuC3Mode::Unknown,
+        }
+    }
+}
+
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    mode: HuC3Mode,
+    rom_bank_number: u8, // 7 bits
+    ram_bank_number: u8, // 2 bits
+
+    // The RTC is a separate chip, talked to by writing commands and reading responses
+    rtc_memory: [u8; 256], // nibbles
+    rtc_address: u8,
+    rtc_command: u8,
+    rtc_response: u8,
+
+    minutes: u16, // minute of the day
+    days: u16,
+    rtc_timestamp: Duration, // since the UNIX epoch
+    clock: Box<dyn Clock>,
+}
+
+impl HuC3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, clock: Box<dyn Clock>) -> Self {
+        HuC3 {
+            rom,
+            ram: vec![0; ram_size],
+
+            mode: HuC3Mode::RAMReadOnly,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+
+            rtc_memory: [0; 256],
+            rtc_address: 0,
+            rtc_command: 0,
+            rtc_response: 0,
+
+            minutes: 0,
+            days: 0,
+            rtc_timestamp: clock.now(),
+            clock,
+        }
+    }
+
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        cartridge::ram_address(&self.ram, self.ram_bank_number as usize, address)
+    }
+
+    /// Bring the clock up to date. It only counts whole minutes.
+    fn refresh_clock(&mut self) {
+        let now = self.clock.now();
+        if now < self.rtc_timestamp {
+            self.rtc_timestamp = now;
+            return;
+        }
+
+        let elapsed = (now - self.rtc_timestamp).as_secs() / 60;
+        let minutes = self.minutes as u64 + elapsed;
+        self.minutes = (minutes % MINUTES_PER_DAY) as u16;
+        self.days = self.days.wrapping_add((minutes / MINUTES_PER_DAY) as u16);
+        self.rtc_timestamp += Duration::from_secs(elapsed * 60);
+    }
+
+    fn read_nibbles(&self, address: usize, count: usize) -> u16 {
+        (0..count).fold(0, |value, i| {
+            value | ((self.rtc_memory[address + i] as u16) << (i * 4))
+        })
+    }
+
+    fn write_nibbles(&mut self, address: usize, count: usize, value: u16) {
+        for i in 0..count {
+            self.rtc_memory[address + i] = ((value >> (i * 4)) & 0x0F) as u8;
+        }
+    }
+
+    fn execute_rtc_command(&mut self, byte: u8) {
+        self.rtc_command = (byte >> 4) & 0x07;
+        let argument = byte & 0x0F;
+
+        match self.rtc_command {
+            // read and increment the address
+            0x1 => {
+                self.rtc_response = self.rtc_memory[self.rtc_address as usize];
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            }
+            // write, then increment the address for 0x3
+            0x2 | 0x3 => {
+                self.rtc_memory[self.rtc_address as usize] = argument;
+                if self.rtc_command == 0x3 {
+                    self.rtc_address = self.rtc_address.wrapping_add(1);
+                }
+            }
+            0x4 => self.rtc_address = (self.rtc_address & 0xF0) | argument,
+            0x5 => self.rtc_address = (self.rtc_address & 0x0F) | (argument << 4),
+            0x6 => match argument {
+                // copy the current time into memory
+                0x0 => {
+                    self.refresh_clock();
+                    self.write_nibbles(TIME_ADDRESS, 3, self.minutes);
+                    self.write_nibbles(TIME_ADDRESS + 3, 4, self.days);
+                }
+                // set the time from memory
+                0x1 => {
+                    self.minutes = self.read_nibbles(TIME_ADDRESS, 3) % MINUTES_PER_DAY as u16;
+                    self.days = self.read_nibbles(TIME_ADDRESS + 3, 4);
+                    self.rtc_timestamp = self.clock.now();
+                }
+                // status, always ready
+                0x2 => self.rtc_response = 0x1,
+                // the remaining commands drive the speaker, which isn't emulated
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl MBC for HuC3 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_rom(&self.rom, self.rom_bank_number as usize, address),
+            0xA000..=0xBFFF => match self.mode {
+                HuC3Mode::RAMReadOnly | HuC3Mode::RAMReadWrite => match self.ram_address(address) {
+                    Some(address) => self.ram[address],
+                    None => 0xFF,
+                },
+                HuC3Mode::RTCResponse => (self.rtc_command << 4) | self.rtc_response,
+                // commands execute immediately, so the RTC is always ready
+                HuC3Mode::RTCSemaphore => 0xFF,
+                // no IR light is ever received
+                HuC3Mode::IR => 0xC0,
+                HuC3Mode::RTCCommand | HuC3Mode::Unknown => 0xFF,
+            },
+            _ => panic!("Invalid HuC3 address: {:#06X}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1FFF => self.mode = HuC3Mode::from_u8(byte),
+            0x2000..=0x3FFF => self.rom_bank_number = (byte & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank_number = byte & 0x03,
+            0x6000..=0x7FFF => {}
+            0xA000..=0xBFFF => match self.mode {
+                HuC3Mode::RAMReadWrite => {
+                    if let Some(address) = self.ram_address(address) {
+                        self.ram[address] = byte;
+                    }
+                }
+                HuC3Mode::RTCCommand => self.execute_rtc_command(byte),
+                // the semaphore and the IR LED don't need to do anything
+                HuC3Mode::RAMReadOnly
+                | HuC3Mode::RTCResponse
+                | HuC3Mode::RTCSemaphore
+                | HuC3Mode::IR
+                | HuC3Mode::Unknown => {}
+            },
+            _ => panic!("Invalid HuC3 address: {:#06X}", address),
+        }
+    }
+
+    fn tick(&mut self, cycles: Cycles) {
+        self.clock.tick(cycles);
+    }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn export_rtc(&self) -> Option<Vec<u8>> {
+        let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+        footer.extend_from_slice(&self.rtc_timestamp.as_secs().to_le_bytes());
+        footer.extend_from_slice(&self.minutes.to_le_bytes());
+        footer.extend_from_slice(&self.days.to_le_bytes());
+        footer.extend_from_slice(&self.read_nibbles(ALARM_ADDRESS, 3).to_le_bytes());
+        footer.extend_from_slice(&self.read_nibbles(ALARM_ADDRESS + 3, 4).to_le_bytes());
+        footer.push(self.rtc_memory[ALARM_ADDRESS + 7] & 0x01);
+        Some(footer)
+    }
+
+    fn import_rtc(&mut self, data: &[u8]) {
+        if data.len() != RTC_FOOTER_SIZE {
+            return;
+        }
+
+        let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        self.rtc_timestamp =
+            Duration::from_secs(u64::from_le_bytes(data[0..8].try_into().unwrap()));
+        self.minutes = word(8) % MINUTES_PER_DAY as u16;
+        self.days = word(10);
+        self.write_nibbles(ALARM_ADDRESS, 3, word(12));
+        self.write_nibbles(ALARM_ADDRESS + 3, 4, word(14));
+        self.rtc_memory[ALARM_ADDRESS + 7] = data[16] & 0x01;
+
+        // account for the time that passed while the emulator wasn't running
+        self.refresh_clock();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::ROM_BANK_SIZE;
+    use crate::cartridge::clock::FixedClock;
+
+    const NOW: Duration = Duration::from_secs(1_700_000_000);
+
+    fn rtc_command(mbc: &mut HuC3, command: u8, argument: u8) {
+        mbc.write_byte(0x0000, HuC3Mode::RTCCommand as u8);
+        mbc.write_byte(0xA000, (command << 4) | argument);
+    }
+
+    fn read_time(mbc: &mut HuC3) -> (u16, u16) {
+        rtc_command(mbc, 0x6, 0x0);
+        rtc_command(mbc, 0x4, 0x0);
+        rtc_command(mbc, 0x5, 0x0);
+
+        let mut nibbles = [0; 7];
+        for nibble in nibbles.iter_mut() {
+            rtc_command(mbc, 0x1, 0x0);
+            mbc.write_byte(0x0000, HuC3Mode::RTCResponse as u8);
+            *nibble = mbc.read_byte(0xA000) & 0x0F;
+        }
+
+        let value = |range: std::ops::Range<usize>| {
+            nibbles[range.clone()]
+                .iter()
+                .enumerate()
+                .fold(0, |value, (i, nibble)| value | (*nibble as u16) << (i * 4))
+        };
+        (value(0..3), value(3..7))
+    }
+
+    #[test]
+    fn test_rtc_commands() {
+        let clock = FixedClock::new(NOW);
+        let mut mbc = HuC3::new(vec![0; 2 * ROM_BANK_SIZE], 0, Box::new(clock.clone()));
+        assert_eq!(read_time(&mut mbc), (0, 0));
+
+        clock.advance(Duration::from_secs(MINUTES_PER_DAY * 60 + 90));
+        assert_eq!(read_time(&mut mbc), (1, 1));
+
+        // set the time to 23:59 on day 5
+        rtc_command(&mut mbc, 0x4, 0x0);
+        rtc_command(&mut mbc, 0x5, 0x0);
+        for nibble in [0xF, 0x9, 0x5, 0x5, 0x0, 0x0, 0x0] {
+            rtc_command(&mut mbc, 0x3, nibble);
+        }
+        rtc_command(&mut mbc, 0x6, 0x1);
+        assert_eq!(read_time(&mut mbc), (1439, 5));
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(read_time(&mut mbc), (0, 6));
+    }
+
+    #[test]
+    fn test_rtc_footer_round_trip() {
+        let clock = FixedClock::new(NOW);
+        let mut mbc = HuC3::new(vec![0; 2 * ROM_BANK_SIZE], 0, Box::new(clock.clone()));
+        clock.advance(Duration::from_secs(120 * 60));
+        let footer = HuC3::new(
+            vec![0; 2 * ROM_BANK_SIZE],
+            0,
+            Box::new(FixedClock::new(NOW)),
+        )
+        .export_rtc()
+        .unwrap();
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+
+        // the two hours since the save was written are added on load
+        mbc.import_rtc(&footer);
+        assert_eq!(read_time(&mut mbc), (120, 0));
+    }
+}
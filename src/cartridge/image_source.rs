@@ -0,0 +1,97 @@
+#[cfg(feature = "png")]
+use std::{fs::File, io, path::Path};
+
+/// Size of the image captured by the Pocket Camera's sensor
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// Picture source for the Pocket Camera's sensor
+pub trait ImageSource {
+    /// Fill `pixels` with a `CAMERA_WIDTH` x `CAMERA_HEIGHT` greyscale image,
+    /// row by row, where 0 is black and 255 is white
+    fn capture(&mut self, pixels: &mut [u8]);
+}
+
+/// Any closure can be used as a source, e.g. to pull frames from a webcam
+impl<F: FnMut(&mut [u8])> ImageSource for F {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        self(pixels)
+    }
+}
+
+/// Greyscale bars with a checkerboard in the middle, so exposure and dithering
+/// can be checked without a real picture
+pub struct TestPattern;
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let (x, y) = (i % CAMERA_WIDTH, i / CAMERA_WIDTH);
+            let checkerboard = (32..96).contains(&x) && (32..80).contains(&y);
+
+            *pixel = if checkerboard {
+                if (x / 8 + y / 8).is_multiple_of(2) {
+                    0xFF
+                } else {
+                    0x00
+                }
+            } else {
+                (x * 0x100 / CAMERA_WIDTH) as u8
+            };
+        }
+    }
+}
+
+/// The same picture every time
+pub struct StaticImage {
+    pixels: Vec<u8>,
+}
+
+impl StaticImage {
+    /// Create from a `width` x `height` greyscale image, scaled to fit the sensor
+    pub fn new(width: usize, height: usize, pixels: &[u8]) -> Self {
+        let pixels = (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+            .map(|i| {
+                let x = (i % CAMERA_WIDTH) * width / CAMERA_WIDTH;
+                let y = (i / CAMERA_WIDTH) * height / CAMERA_HEIGHT;
+                pixels[y * width + x]
+            })
+            .collect();
+        StaticImage { pixels }
+    }
+
+    /// Load a PNG, converting it to greyscale
+    #[cfg(feature = "png")]
+    pub fn from_png(path: &Path) -> io::Result<Self> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        let channels = info.color_type.samples();
+        let greyscale: Vec<u8> = buffer[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|pixel| match pixel {
+                // ITU-R BT.601 luma, ignoring any alpha
+                [r, g, b, ..] => {
+                    ((*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000) as u8
+                }
+                [luma, ..] => *luma,
+                [] => 0,
+            })
+            .collect();
+
+        Ok(StaticImage::new(
+            info.width as usize,
+            info.height as usize,
+            &greyscale,
+        ))
+    }
+}
+
+impl ImageSource for StaticImage {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        pixels.copy_from_slice(&self.pixels);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::cartridge::{MBC, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::cartridge::{self, MBC, ROM_BANK_SIZE, load_ram, read_rom};
 
 enum MBC1BankingMode {
     Simple = 0,
@@ -8,8 +8,11 @@ enum MBC1BankingMode {
 pub struct MBC1 {
     ram_enable: bool,
     rom_bank_number: u8,           // 5 bits
-    ram_bank_number: u8,           // 2 bits
+    ram_bank_number: u8,           // 2 bits, also the upper ROM bank bits
     banking_mode: MBC1BankingMode, // 1 bit
+    // MBC1M multicarts only wire 4 bits of the ROM bank number,
+    // so the upper bits are shifted by 4 instead of 5
+    multicart: bool,
     rom: Vec<u8>,
     ram: Vec<u8>,
 }
@@ -23,33 +26,69 @@ impl MBC1 {
             rom_bank_number: 0,
             ram_bank_number: 0,
             banking_mode: MBC1BankingMode::Simple,
+            multicart: false,
         }
     }
+
+    pub fn new_multicart(rom: Vec<u8>, ram_size: usize) -> Self {
+        MBC1 {
+            multicart: true,
+            ..MBC1::new(rom, ram_size)
+        }
+    }
+
+    fn upper_bits_shift(&self) -> usize {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        (self.rom.len() / ROM_BANK_SIZE).max(1)
+    }
+
+    /// Bank mapped to 0x0000 - 0x3FFF. In advanced mode the upper bits apply here too,
+    /// which lets ROMs of 1 MiB and above reach banks 0x20, 0x40 and 0x60.
+    fn lower_rom_bank(&self) -> usize {
+        let bank = match self.banking_mode {
+            MBC1BankingMode::Simple => 0,
+            MBC1BankingMode::Advanced => (self.ram_bank_number as usize) << self.upper_bits_shift(),
+        };
+        bank % self.rom_bank_count()
+    }
+
+    /// Bank mapped to 0x4000 - 0x7FFF
+    fn upper_rom_bank(&self) -> usize {
+        // 0 is remapped to 1 before the upper bits are applied,
+        // so banks 0x20, 0x40 and 0x60 map to 0x21, 0x41 and 0x61
+        let mut lower_bits = self.rom_bank_number.max(1) as usize;
+        if self.multicart {
+            lower_bits &= 0x0F;
+        }
+        (((self.ram_bank_number as usize) << self.upper_bits_shift()) | lower_bits)
+            % self.rom_bank_count()
+    }
+
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        if !self.ram_enable {
+            return None;
+        }
+
+        let bank = match self.banking_mode {
+            MBC1BankingMode::Simple => 0,
+            MBC1BankingMode::Advanced => self.ram_bank_number as usize,
+        };
+        cartridge::ram_address(&self.ram, bank, address)
+    }
 }
 
 impl MBC for MBC1 {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3FFF => self.rom[address as usize],
-            0x4000..=0x7FFF => {
-                self.rom
-                    [(self.rom_bank_number.max(1) - 1) as usize * ROM_BANK_SIZE + address as usize]
-            }
-            0xA000..=0xBFFF => {
-                if self.ram_enable {
-                    let address = match self.banking_mode {
-                        MBC1BankingMode::Simple => address as usize - 0xA000,
-                        MBC1BankingMode::Advanced => {
-                            self.ram_bank_number as usize * RAM_BANK_SIZE + address as usize
-                                - 0xA000
-                        }
-                    };
-
-                    self.ram[address]
-                } else {
-                    0xFF
-                }
-            }
+            0x0000..=0x3FFF => read_rom(&self.rom, self.lower_rom_bank(), address),
+            0x4000..=0x7FFF => read_rom(&self.rom, self.upper_rom_bank(), address),
+            0xA000..=0xBFFF => match self.ram_address(address) {
+                Some(address) => self.ram[address],
+                None => 0xFF,
+            },
             _ => panic!("Invalid MBC1 Address: {:#06X}", address),
         }
     }
@@ -71,21 +110,101 @@ impl MBC for MBC1 {
                 };
             }
             0xA000..=0xBFFF => {
-                if self.ram_enable {
-                    let address = match self.banking_mode {
-                        MBC1BankingMode::Simple => address as usize - 0xA000,
-                        MBC1BankingMode::Advanced => {
-                            self.ram_bank_number as usize * RAM_BANK_SIZE + address as usize
-                                - 0xA000
-                        }
-                    };
-
-                    if address < self.ram.len() {
-                        self.ram[address] = byte;
-                    }
+                if let Some(address) = self.ram_address(address) {
+                    self.ram[address] = byte;
                 }
             }
             _ => panic!("Invalid MBC1 Address: {:#06X}", address),
         }
     }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, banked_rom};
+
+    #[test]
+    fn test_bank_zero_remap() {
+        let mut mbc = MBC1::new(banked_rom(128), 0);
+        assert_eq!(mbc.read_byte(0x4000), 0x01);
+
+        mbc.write_byte(0x2000, 0x00);
+        mbc.write_byte(0x4000, 0x01);
+        assert_eq!(mbc.read_byte(0x4000), 0x21);
+
+        mbc.write_byte(0x2000, 0x05);
+        assert_eq!(mbc.read_byte(0x4000), 0x25);
+    }
+
+    #[test]
+    fn test_advanced_mode_lower_bank() {
+        let mut mbc = MBC1::new(banked_rom(128), 0);
+        mbc.write_byte(0x4000, 0x02);
+        assert_eq!(mbc.read_byte(0x0000), 0x00);
+
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(mbc.read_byte(0x0000), 0x40);
+        assert_eq!(mbc.read_byte(0x4000), 0x41);
+    }
+
+    #[test]
+    fn test_bank_masking() {
+        // 256 KiB ROM, only 16 banks
+        let mut mbc = MBC1::new(banked_rom(16), 0);
+        mbc.write_byte(0x2000, 0x12);
+        assert_eq!(mbc.read_byte(0x4000), 0x02);
+
+        // the upper bits are ignored by small ROMs
+        mbc.write_byte(0x4000, 0x03);
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(mbc.read_byte(0x0000), 0x00);
+        assert_eq!(mbc.read_byte(0x4000), 0x02);
+    }
+
+    #[test]
+    fn test_multicart_banking() {
+        let mut mbc = MBC1::new_multicart(banked_rom(64), 0);
+        mbc.write_byte(0x4000, 0x01);
+        mbc.write_byte(0x2000, 0x12);
+        assert_eq!(mbc.read_byte(0x4000), 0x12);
+
+        // bank 0x10 still counts as non-zero, but bit 4 isn't wired
+        mbc.write_byte(0x2000, 0x10);
+        assert_eq!(mbc.read_byte(0x4000), 0x10);
+
+        mbc.write_byte(0x6000, 0x01);
+        mbc.write_byte(0x4000, 0x02);
+        assert_eq!(mbc.read_byte(0x0000), 0x20);
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut mbc = MBC1::new(banked_rom(4), 4 * RAM_BANK_SIZE);
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x02);
+        mbc.write_byte(0xA000, 0x12);
+        // simple mode always uses bank 0
+        assert_eq!(mbc.ram[0], 0x12);
+
+        mbc.write_byte(0x6000, 0x01);
+        mbc.write_byte(0xA000, 0x34);
+        assert_eq!(mbc.ram[2 * RAM_BANK_SIZE], 0x34);
+
+        // an 8 KiB RAM ignores the bank number
+        let mut mbc = MBC1::new(banked_rom(4), RAM_BANK_SIZE);
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x6000, 0x01);
+        mbc.write_byte(0x4000, 0x03);
+        mbc.write_byte(0xA001, 0x56);
+        assert_eq!(mbc.read_byte(0xA001), 0x56);
+    }
 }
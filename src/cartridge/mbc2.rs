@@ -0,0 +1,118 @@
+use crate::{
+    cartridge::{MBC, read_rom},
+    utils::is_set,
+};
+
+// 512 half bytes of RAM are built into the MBC2 itself
+const MBC2_RAM_SIZE: usize = 512;
+
+pub struct MBC2 {
+    rom: Vec<u8>,
+    ram: [u8; MBC2_RAM_SIZE],
+
+    ram_enable: bool,
+    rom_bank_number: u8, // 4 bits
+}
+
+impl MBC2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        MBC2 {
+            rom,
+            ram: [0; MBC2_RAM_SIZE],
+
+            ram_enable: false,
+            rom_bank_number: 1,
+        }
+    }
+
+    /// Only the bottom 9 bits are decoded, so RAM is mirrored across 0xA000 - 0xBFFF
+    fn ram_address(address: u16) -> usize {
+        (address & 0x01FF) as usize
+    }
+}
+
+impl MBC for MBC2 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_rom(&self.rom, self.rom_bank_number as usize, address),
+            0xA000..=0xBFFF => {
+                if self.ram_enable {
+                    // the upper nibble is not connected and reads back as 1s
+                    0xF0 | self.ram[MBC2::ram_address(address)]
+                } else {
+                    0xFF
+                }
+            }
+            _ => panic!("Invalid MBC2 address: {:#06X}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            // bit 8 of the address selects between the two registers
+            0x0000..=0x3FFF => {
+                if is_set((address >> 8) as u8, 0) {
+                    self.rom_bank_number = (byte & 0x0F).max(1);
+                } else {
+                    self.ram_enable = (byte & 0x0F) == 0x0A;
+                }
+            }
+            0x4000..=0x7FFF => {}
+            0xA000..=0xBFFF => {
+                if self.ram_enable {
+                    self.ram[MBC2::ram_address(address)] = byte & 0x0F;
+                }
+            }
+            _ => panic!("Invalid MBC2 address: {:#06X}", address),
+        }
+    }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        for (ram, byte) in self.ram.iter_mut().zip(data) {
+            *ram = byte & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{ROM_BANK_SIZE, banked_rom};
+
+    #[test]
+    fn test_register_select_by_address_bit_8() {
+        let mut mbc = MBC2::new(banked_rom(16));
+
+        // bit 8 clear: RAM enable, even in the upper half of the register range
+        mbc.write_byte(0x3000, 0x0A);
+        assert!(mbc.ram_enable);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+
+        mbc.write_byte(0x2100, 0x05);
+        assert_eq!(mbc.read_byte(0x4000), 5);
+        assert!(mbc.ram_enable);
+
+        mbc.write_byte(0x0100, 0x00);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+    }
+
+    #[test]
+    fn test_half_byte_ram() {
+        let mut mbc = MBC2::new(vec![0; 2 * ROM_BANK_SIZE]);
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0xA000, 0x3C);
+
+        assert_eq!(mbc.read_byte(0xA000), 0xFC);
+        // mirrored every 512 bytes
+        assert_eq!(mbc.read_byte(0xA200), 0xFC);
+        assert_eq!(mbc.read_byte(0xBE00), 0xFC);
+
+        mbc.write_byte(0x0000, 0x00);
+        assert_eq!(mbc.read_byte(0xA000), 0xFF);
+    }
+}
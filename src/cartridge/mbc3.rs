@@ -1,10 +1,16 @@
-use std::time::Instant;
+use std::time::Duration;
 
 use crate::{
-    cartridge::{MBC, RAM_BANK_SIZE, ROM_BANK_SIZE},
-    utils::{is_set, reset_bit, set_bit},
+    cartridge::{self, MBC, clock::Clock, load_ram, read_rom},
+    cpu::Cycles,
+    utils::{is_set, set_bit},
 };
 
+// Size of the BGB/VBA-M RTC footer appended to save files, 44 byte saves use a 32 bit timestamp
+const RTC_FOOTER_SIZE: usize = 48;
+const RTC_FOOTER_SIZE_32_BIT: usize = 44;
+
+#[derive(Clone, Copy)]
 struct RTCRegs {
     seconds: u8,
     minutes: u8,
@@ -13,6 +19,59 @@ struct RTCRegs {
     day_counter_high: u8,
 }
 
+impl RTCRegs {
+    fn halted(&self) -> bool {
+        is_set(self.day_counter_high, 6)
+    }
+
+    fn days(&self) -> u64 {
+        ((self.day_counter_high as u64 & 0x01) << 8) | self.day_counter_low as u64
+    }
+
+    /// Advance the clock, setting the day counter carry when it overflows past 511 days
+    fn advance(&mut self, seconds: u64) {
+        let seconds = self.seconds as u64 + seconds;
+        self.seconds = (seconds % 60) as u8;
+        let minutes = self.minutes as u64 + seconds / 60;
+        self.minutes = (minutes % 60) as u8;
+        let hours = self.hours as u64 + minutes / 60;
+        self.hours = (hours % 24) as u8;
+        let days = self.days() + hours / 24;
+
+        self.day_counter_low = (days & 0xFF) as u8;
+        self.day_counter_high = (self.day_counter_high & 0xFE) | ((days >> 8) & 0x01) as u8;
+        if days > 0x1FF {
+            self.day_counter_high = set_bit(self.day_counter_high, 7);
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 20] {
+        let mut bytes = [0; 20];
+        let regs = [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.day_counter_low,
+            self.day_counter_high,
+        ];
+        for (chunk, reg) in bytes.chunks_exact_mut(4).zip(regs) {
+            chunk.copy_from_slice(&(reg as u32).to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let reg = |i: usize| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()) as u8;
+        RTCRegs {
+            seconds: reg(0) & 0x3F,
+            minutes: reg(1) & 0x3F,
+            hours: reg(2) & 0x1F,
+            day_counter_low: reg(3),
+            day_counter_high: reg(4) & 0xC1,
+        }
+    }
+}
+
 pub struct MBC3 {
     rom: Vec<u8>,
     ram: Vec<u8>,
@@ -21,14 +80,25 @@ pub struct MBC3 {
     ram_rtc_enable: bool,
     ram_rtc_select: u8,
 
-    rtc: RTCRegs,
+    has_rtc: bool,
+    rtc: RTCRegs, // live registers, up to date as of `rtc_timestamp`
+    latched_rtc: RTCRegs,
     latch: u8,
 
-    init_time: Instant,
+    rtc_timestamp: Duration, // since the UNIX epoch
+    clock: Box<dyn Clock>,
 }
 
 impl MBC3 {
-    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool, clock: Box<dyn Clock>) -> Self {
+        let rtc = RTCRegs {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            day_counter_low: 0,
+            day_counter_high: 0,
+        };
+
         MBC3 {
             rom,
             ram: vec![0; ram_size],
@@ -37,71 +107,73 @@ impl MBC3 {
             ram_rtc_enable: false,
             ram_rtc_select: 0,
 
-            rtc: RTCRegs {
-                seconds: 0,
-                minutes: 0,
-                hours: 0,
-                day_counter_low: 0,
-                day_counter_high: 0,
-            },
+            has_rtc,
+            rtc,
+            latched_rtc: rtc,
             latch: 0xFF,
-            init_time: Instant::now(),
+            rtc_timestamp: clock.now(),
+            clock,
         }
     }
 
+    /// Bring the live RTC registers up to date with the clock
     fn refresh_clock(&mut self) {
-        // timer is stopped
-        if is_set(self.rtc.day_counter_high, 6) {
+        let now = self.clock.now();
+        if self.rtc.halted() || now < self.rtc_timestamp {
+            self.rtc_timestamp = now;
             return;
         }
 
-        self.rtc.day_counter_high = set_bit(self.rtc.day_counter_high, 6);
-
-        let duration = self.init_time.elapsed();
+        // keep the sub-second remainder so the clock doesn't drift
+        let elapsed = (now - self.rtc_timestamp).as_secs();
+        self.rtc.advance(elapsed);
+        self.rtc_timestamp += Duration::from_secs(elapsed);
+    }
 
-        let secs = duration.as_secs();
-        self.rtc.seconds = (secs % 60) as u8;
-        let mins = secs / 60;
-        self.rtc.minutes = (mins % 60) as u8;
-        let hours = mins / 60;
-        self.rtc.hours = (hours % 24) as u8;
-        let days = hours / 24;
-        self.rtc.day_counter_low = (days & 0xFF) as u8;
+    fn write_rtc(&mut self, byte: u8) {
+        self.refresh_clock();
 
-        if days & 0x0100 != 0 {
-            self.rtc.day_counter_high = set_bit(self.rtc.day_counter_high, 0);
-        }
-        if days > 0x1FF {
-            self.rtc.day_counter_high = set_bit(self.rtc.day_counter_high, 7);
+        match self.ram_rtc_select {
+            0x08 => {
+                self.rtc.seconds = byte & 0x3F;
+                // writing the seconds resets the sub-second counter
+                self.rtc_timestamp = self.clock.now();
+            }
+            0x09 => self.rtc.minutes = byte & 0x3F,
+            0x0A => self.rtc.hours = byte & 0x1F,
+            0x0B => self.rtc.day_counter_low = byte,
+            0x0C => self.rtc.day_counter_high = byte & 0xC1,
+            // nothing is mapped past the RTC registers
+            _ => return,
         }
 
-        self.rtc.day_counter_high = reset_bit(self.rtc.day_counter_high, 6);
+        self.latched_rtc = self.rtc;
+    }
+
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        cartridge::ram_address(&self.ram, self.ram_rtc_select as usize, address)
     }
 }
 
 impl MBC for MBC3 {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3FFF => self.rom[address as usize],
-            0x4000..=0x7FFF => {
-                self.rom
-                    [(self.rom_bank_number.max(1) - 1) as usize * ROM_BANK_SIZE + address as usize]
-            }
+            0x0000..=0x3FFF => read_rom(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_rom(&self.rom, self.rom_bank_number.max(1) as usize, address),
 
             0xA000..=0xBFFF => {
                 if self.ram_rtc_enable {
                     match self.ram_rtc_select {
-                        0x00..=0x07 => {
-                            self.ram[self.ram_rtc_select as usize * RAM_BANK_SIZE
-                                + address as usize
-                                - 0xA000]
-                        }
-                        0x08 => self.rtc.seconds,
-                        0x09 => self.rtc.minutes,
-                        0x0A => self.rtc.hours,
-                        0x0B => self.rtc.day_counter_low,
-                        0x0C => self.rtc.day_counter_high,
-                        _ => panic!("Invalid ram_rtc_select: {:#04X}", self.ram_rtc_select),
+                        0x00..=0x07 => match self.ram_address(address) {
+                            Some(address) => self.ram[address],
+                            None => 0xFF,
+                        },
+                        0x08 => self.latched_rtc.seconds,
+                        0x09 => self.latched_rtc.minutes,
+                        0x0A => self.latched_rtc.hours,
+                        0x0B => self.latched_rtc.day_counter_low,
+                        0x0C => self.latched_rtc.day_counter_high,
+                        _ => 0xFF,
                     }
                 } else {
                     0xFF
@@ -124,6 +196,7 @@ impl MBC for MBC3 {
             0x6000..=0x7FFF => {
                 if self.latch == 0x00 && byte == 0x01 {
                     self.refresh_clock();
+                    self.latched_rtc = self.rtc;
                 }
                 self.latch = byte;
             }
@@ -131,27 +204,212 @@ impl MBC for MBC3 {
                 if self.ram_rtc_enable {
                     match self.ram_rtc_select {
                         0x00..=0x07 => {
-                            self.ram[self.ram_rtc_select as usize * RAM_BANK_SIZE
-                                + address as usize
-                                - 0xA000] = byte
-                        }
-                        0x08..=0x0C => {
-                            self.rtc.day_counter_high = set_bit(self.rtc.day_counter_high, 6);
-                            match self.ram_rtc_select {
-                                0x08 => self.rtc.seconds = byte,
-                                0x09 => self.rtc.minutes = byte,
-                                0x0A => self.rtc.hours = byte,
-                                0x0B => self.rtc.day_counter_low = byte,
-                                0x0C => self.rtc.day_counter_high = byte,
-                                _ => panic!("Invalid ram_rtc_select: {:#04X}", self.ram_rtc_select),
+                            if let Some(address) = self.ram_address(address) {
+                                self.ram[address] = byte;
                             }
-                            self.rtc.day_counter_high = reset_bit(self.rtc.day_counter_high, 6);
                         }
-                        _ => panic!("Invalid ram_rtc_select: {:#04X}", self.ram_rtc_select),
+                        0x08..=0x0C => self.write_rtc(byte),
+                        _ => {}
                     }
                 }
             }
             _ => panic!("Invalid MBC3 address: {:#06X}", address),
         }
     }
+
+    fn tick(&mut self, cycles: Cycles) {
+        self.clock.tick(cycles);
+    }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn export_rtc(&self) -> Option<Vec<u8>> {
+        if !self.has_rtc {
+            return None;
+        }
+
+        let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+        footer.extend_from_slice(&self.rtc.to_bytes());
+        footer.extend_from_slice(&self.latched_rtc.to_bytes());
+        footer.extend_from_slice(&self.rtc_timestamp.as_secs().to_le_bytes());
+        Some(footer)
+    }
+
+    fn import_rtc(&mut self, data: &[u8]) {
+        if !self.has_rtc {
+            return;
+        }
+
+        let timestamp = match data.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            RTC_FOOTER_SIZE_32_BIT => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+            _ => return,
+        };
+
+        self.rtc = RTCRegs::from_bytes(&data[0..20]);
+        self.latched_rtc = RTCRegs::from_bytes(&data[20..40]);
+        self.rtc_timestamp = Duration::from_secs(timestamp);
+
+        // account for the time that passed while the emulator wasn't running
+        self.refresh_clock();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{
+        RAM_BANK_SIZE,
+        clock::{CycleClock, FixedClock},
+    };
+    use crate::cpu::CPU_CLOCK_SPEED;
+
+    const NOW: Duration = Duration::from_secs(1_700_000_000);
+
+    fn footer(rtc: RTCRegs, timestamp: Duration) -> Vec<u8> {
+        let mut footer = rtc.to_bytes().to_vec();
+        footer.extend_from_slice(&rtc.to_bytes());
+        footer.extend_from_slice(&timestamp.as_secs().to_le_bytes());
+        footer
+    }
+
+    #[test]
+    fn test_rtc_day_counter_carry() {
+        let mut rtc = RTCRegs {
+            seconds: 59,
+            minutes: 59,
+            hours: 23,
+            day_counter_low: 0xFF,
+            day_counter_high: 0x01,
+        };
+
+        rtc.advance(1);
+        assert_eq!(rtc.seconds, 0);
+        assert_eq!(rtc.minutes, 0);
+        assert_eq!(rtc.hours, 0);
+        assert_eq!(rtc.days(), 0);
+        assert!(is_set(rtc.day_counter_high, 7));
+    }
+
+    #[test]
+    fn test_rtc_footer_round_trip() {
+        let mut mbc = MBC3::new(vec![0; 0x8000], 0, true, Box::new(FixedClock::new(NOW)));
+        let rtc = RTCRegs {
+            seconds: 12,
+            minutes: 34,
+            hours: 5,
+            day_counter_low: 0x42,
+            day_counter_high: 0x40, // halted
+        };
+
+        // time doesn't pass while the clock is halted
+        mbc.import_rtc(&footer(rtc, NOW - Duration::from_secs(3600)));
+        let exported = mbc.export_rtc().unwrap();
+        assert_eq!(exported.len(), RTC_FOOTER_SIZE);
+        assert_eq!(exported[..40], footer(rtc, Duration::ZERO)[..40]);
+    }
+
+    #[test]
+    fn test_rtc_advances_by_elapsed_time() {
+        let mut mbc = MBC3::new(vec![0; 0x8000], 0, true, Box::new(FixedClock::new(NOW)));
+        let rtc = RTCRegs {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            day_counter_low: 0,
+            day_counter_high: 0,
+        };
+
+        // one day and one hour ago
+        mbc.import_rtc(&footer(rtc, NOW - Duration::from_secs(90000)));
+        assert_eq!(mbc.rtc.days(), 1);
+        assert_eq!(mbc.rtc.hours, 1);
+        assert_eq!(mbc.rtc.minutes, 0);
+        assert_eq!(mbc.rtc.seconds, 0);
+    }
+
+    #[test]
+    fn test_rtc_latch() {
+        let clock = FixedClock::new(NOW);
+        let mut mbc = MBC3::new(vec![0; 0x8000], 0, true, Box::new(clock.clone()));
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x08);
+
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(mbc.read_byte(0xA000), 0);
+
+        mbc.write_byte(0x6000, 0x00);
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(mbc.read_byte(0xA000), 1);
+        mbc.write_byte(0x4000, 0x09);
+        assert_eq!(mbc.read_byte(0xA000), 1);
+
+        // halting stops the clock
+        mbc.write_byte(0x4000, 0x0C);
+        mbc.write_byte(0xA000, 0x40);
+        clock.advance(Duration::from_secs(3600));
+        mbc.write_byte(0x6000, 0x00);
+        mbc.write_byte(0x6000, 0x01);
+        mbc.write_byte(0x4000, 0x09);
+        assert_eq!(mbc.read_byte(0xA000), 1);
+    }
+
+    #[test]
+    fn test_ram_without_banks() {
+        let mut mbc = MBC3::new(vec![0; 0x8000], 0, true, Box::new(FixedClock::new(NOW)));
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0xA000, 0x12);
+        assert_eq!(mbc.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_ram_bank_mirroring() {
+        let mut mbc = MBC3::new(
+            vec![0; 0x8000],
+            RAM_BANK_SIZE,
+            false,
+            Box::new(FixedClock::new(NOW)),
+        );
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x03);
+        mbc.write_byte(0xA000, 0x12);
+
+        mbc.write_byte(0x4000, 0x00);
+        assert_eq!(mbc.read_byte(0xA000), 0x12);
+    }
+
+    #[test]
+    fn test_unmapped_rtc_select() {
+        let mut mbc = MBC3::new(
+            vec![0; 0x8000],
+            RAM_BANK_SIZE,
+            true,
+            Box::new(FixedClock::new(NOW)),
+        );
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x0D);
+        mbc.write_byte(0xA000, 0x12);
+        assert_eq!(mbc.read_byte(0xA000), 0xFF);
+
+        mbc.write_byte(0x4000, 0x00);
+        assert_eq!(mbc.read_byte(0xA000), 0x00);
+    }
+
+    #[test]
+    fn test_rtc_cycle_clock() {
+        let mut mbc = MBC3::new(vec![0; 0x8000], 0, true, Box::new(CycleClock::new(NOW)));
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x08);
+
+        mbc.tick(CPU_CLOCK_SPEED * 2);
+        mbc.write_byte(0x6000, 0x00);
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(mbc.read_byte(0xA000), 2);
+    }
 }
@@ -0,0 +1,143 @@
+use crate::{
+    cartridge::{self, MBC, load_ram, read_rom},
+    utils::is_set,
+};
+
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    ram_enable: bool,
+    rom_bank_number: u16, // 9 bits
+    ram_bank_number: u8,  // 4 bits, 3 on rumble cartridges
+
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        MBC5 {
+            rom,
+            ram: vec![0; ram_size],
+
+            ram_enable: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        if !self.ram_enable {
+            return None;
+        }
+        cartridge::ram_address(&self.ram, self.ram_bank_number as usize, address)
+    }
+}
+
+impl MBC for MBC5 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom(&self.rom, 0, address),
+            // unlike MBC1 and MBC3, bank 0 can be mapped here
+            0x4000..=0x7FFF => read_rom(&self.rom, self.rom_bank_number as usize, address),
+            0xA000..=0xBFFF => match self.ram_address(address) {
+                Some(address) => self.ram[address],
+                None => 0xFF,
+            },
+            _ => panic!("Invalid MBC5 address: {:#06X}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enable = (byte & 0x0F) == 0x0A,
+            0x2000..=0x2FFF => {
+                self.rom_bank_number = (self.rom_bank_number & 0x100) | byte as u16;
+            }
+            0x3000..=0x3FFF => {
+                self.rom_bank_number = (self.rom_bank_number & 0xFF) | ((byte as u16 & 0x01) << 8);
+            }
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    // bit 3 drives the rumble motor instead of selecting a bank
+                    self.rumble = is_set(byte, 3);
+                    self.ram_bank_number = byte & 0x07;
+                } else {
+                    self.ram_bank_number = byte & 0x0F;
+                }
+            }
+            0x6000..=0x7FFF => {}
+            0xA000..=0xBFFF => {
+                if let Some(address) = self.ram_address(address) {
+                    self.ram[address] = byte;
+                }
+            }
+            _ => panic!("Invalid MBC5 address: {:#06X}", address),
+        }
+    }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, banked_rom};
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = MBC5::new(banked_rom(512), 0, false);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+
+        mbc.write_byte(0x2000, 0x00);
+        assert_eq!(mbc.read_byte(0x4000), 0);
+
+        mbc.write_byte(0x2000, 0x05);
+        mbc.write_byte(0x3000, 0x01);
+        assert_eq!(mbc.rom_bank_number, 0x105);
+        assert_eq!(mbc.read_byte(0x7FFF), 0x05);
+
+        // banks past the end of the ROM wrap around
+        let mut mbc = MBC5::new(banked_rom(4), 0, false);
+        mbc.write_byte(0x2000, 0x06);
+        assert_eq!(mbc.read_byte(0x4000), 2);
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut mbc = MBC5::new(banked_rom(2), 16 * RAM_BANK_SIZE, false);
+        mbc.write_byte(0xA000, 0x12);
+        assert_eq!(mbc.read_byte(0xA000), 0xFF);
+
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x0F);
+        mbc.write_byte(0xA000, 0x12);
+        assert_eq!(mbc.ram[15 * RAM_BANK_SIZE], 0x12);
+    }
+
+    #[test]
+    fn test_rumble() {
+        let mut mbc = MBC5::new(banked_rom(2), 4 * RAM_BANK_SIZE, true);
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x09);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.ram_bank_number, 1);
+
+        mbc.write_byte(0x4000, 0x01);
+        assert!(!mbc.rumble());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::{
+    cartridge::{MBC, read_rom},
+    utils::is_set,
+};
+
+// The 93LC56 holds 128 16 bit words
+const EEPROM_WORDS: usize = 128;
+
+// Bits of the EEPROM register at 0xAx8x
+const EEPROM_CS: u8 = 7;
+const EEPROM_CLK: u8 = 6;
+const EEPROM_DI: u8 = 1;
+
+// Accelerometer readings when the cartridge is held flat, and how much 1g changes them
+const ACCELEROMETER_CENTER: u16 = 0x81D0;
+const ACCELEROMETER_GRAVITY: f32 = 0x70 as f32;
+const ACCELEROMETER_ERASED: u16 = 0x8000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum EepromState {
+    Idle,    // waiting for a start bit
+    Command, // shifting in the opcode and address
+    Read,    // shifting out words
+    Write,   // shifting in a word
+}
+
+/// 93LC56 serial EEPROM, organised as 16 bit words and bit banged by the game
+struct Eeprom {
+    data: [u16; EEPROM_WORDS],
+
+    cs: bool,
+    clk: bool,
+    di: bool,
+    do_: bool,
+
+    state: EepromState,
+    shift_register: u16,
+    bit_count: u8,
+    address: u8,
+    write_all: bool,
+    write_enable: bool,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Eeprom {
+            data: [0xFFFF; EEPROM_WORDS],
+
+            cs: false,
+            clk: false,
+            di: false,
+            do_: true,
+
+            state: EepromState::Idle,
+            shift_register: 0,
+            bit_count: 0,
+            address: 0,
+            write_all: false,
+            write_enable: false,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << EEPROM_CS
+            | (self.clk as u8) << EEPROM_CLK
+            | (self.di as u8) << EEPROM_DI
+            | self.do_ as u8
+    }
+
+    fn write(&mut self, byte: u8) {
+        let cs = is_set(byte, EEPROM_CS);
+        let clk = is_set(byte, EEPROM_CLK);
+        self.di = is_set(byte, EEPROM_DI);
+
+        if !cs {
+            // deselecting aborts whatever command was in progress
+            self.state = EepromState::Idle;
+        } else if clk && !self.clk {
+            self.clock();
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    /// Rising edge of the clock while the chip is selected
+    fn clock(&mut self) {
+        match self.state {
+            EepromState::Idle => {
+                if self.di {
+                    self.state = EepromState::Command;
+                    self.shift_register = 0;
+                    self.bit_count = 0;
+                }
+            }
+            EepromState::Command => {
+                self.shift_in();
+                // 2 opcode bits followed by 8 address bits
+                if self.bit_count == 10 {
+                    self.execute(self.shift_register);
+                }
+            }
+            EepromState::Read => {
+                self.do_ = is_set((self.shift_register >> 8) as u8, 7);
+                self.shift_register <<= 1;
+                self.bit_count += 1;
+                // keep reading from the next address until deselected
+                if self.bit_count == 16 {
+                    self.address = (self.address + 1) % EEPROM_WORDS as u8;
+                    self.shift_register = self.data[self.address as usize];
+                    self.bit_count = 0;
+                }
+            }
+            EepromState::Write => {
+                self.shift_in();
+                if self.bit_count == 16 {
+                    if self.write_enable {
+                        if self.write_all {
+                            self.data.fill(self.shift_register);
+                        } else {
+                            self.data[self.address as usize] = self.shift_register;
+                        }
+                    }
+                    // writes finish instantly, so always report ready
+                    self.do_ = true;
+                    self.state = EepromState::Idle;
+                }
+            }
+        }
+    }
+
+    fn shift_in(&mut self) {
+        self.shift_register = (self.shift_register << 1) | self.di as u16;
+        self.bit_count += 1;
+    }
+
+    fn execute(&mut self, command: u16) {
+        self.address = (command & 0x7F) as u8;
+        self.shift_register = 0;
+        self.bit_count = 0;
+        self.state = EepromState::Idle;
+
+        match (command >> 8) & 0x03 {
+            // READ, a dummy 0 comes before the data
+            0b10 => {
+                self.do_ = false;
+                self.shift_register = self.data[self.address as usize];
+                self.state = EepromState::Read;
+            }
+            // WRITE
+            0b01 => {
+                self.write_all = false;
+                self.state = EepromState::Write;
+            }
+            // ERASE
+            0b11 => {
+                if self.write_enable {
+                    self.data[self.address as usize] = 0xFFFF;
+                }
+                self.do_ = true;
+            }
+            // the top address bits select one of the extended commands
+            _ => match (command >> 6) & 0x03 {
+                // EWDS
+                0b00 => self.write_enable = false,
+                // WRAL
+                0b01 => {
+                    self.write_all = true;
+                    self.state = EepromState::Write;
+                }
+                // ERAL
+                0b10 => {
+                    if self.write_enable {
+                        self.data.fill(0xFFFF);
+                    }
+                    self.do_ = true;
+                }
+                // EWEN
+                _ => self.write_enable = true,
+            },
+        }
+    }
+}
+
+pub struct MBC7 {
+    rom: Vec<u8>,
+    eeprom: Eeprom,
+
+    // both need to be set to access 0xA000 - 0xAFFF
+    ram_enable_1: bool,
+    ram_enable_2: bool,
+    rom_bank_number: u8, // 7 bits
+
+    tilt: (f32, f32), // in g, set by the frontend
+    accelerometer_x: u16,
+    accelerometer_y: u16,
+    accelerometer_erased: bool,
+}
+
+impl MBC7 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        MBC7 {
+            rom,
+            eeprom: Eeprom::new(),
+
+            ram_enable_1: false,
+            ram_enable_2: false,
+            rom_bank_number: 1,
+
+            tilt: (0.0, 0.0),
+            accelerometer_x: ACCELEROMETER_ERASED,
+            accelerometer_y: ACCELEROMETER_ERASED,
+            accelerometer_erased: false,
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable_1 && self.ram_enable_2
+    }
+
+    fn accelerometer_value(tilt: f32) -> u16 {
+        (ACCELEROMETER_CENTER as f32 + tilt * ACCELEROMETER_GRAVITY) as u16
+    }
+}
+
+impl MBC for MBC7 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => read_rom(&self.rom, 0, address),
+            0x4000..=0x7FFF => read_rom(&self.rom, self.rom_bank_number as usize, address),
+            0xA000..=0xAFFF if self.ram_enabled() => match (address >> 4) & 0x0F {
+                0x2 => self.accelerometer_x as u8,
+                0x3 => (self.accelerometer_x >> 8) as u8,
+                0x4 => self.accelerometer_y as u8,
+                0x5 => (self.accelerometer_y >> 8) as u8,
+                // the unused Z axis
+                0x6 => 0x00,
+                0x8 => self.eeprom.read(),
+                _ => 0xFF,
+            },
+            0xA000..=0xBFFF => 0xFF,
+            _ => panic!("Invalid MBC7 address: {:#06X}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enable_1 = (byte & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank_number = byte & 0x7F,
+            0x4000..=0x5FFF => self.ram_enable_2 = byte == 0x40,
+            0x6000..=0x7FFF => {}
+            0xA000..=0xAFFF if self.ram_enabled() => match (address >> 4) & 0x0F {
+                0x0 if byte == 0x55 => {
+                    self.accelerometer_x = ACCELEROMETER_ERASED;
+                    self.accelerometer_y = ACCELEROMETER_ERASED;
+                    self.accelerometer_erased = true;
+                }
+                // latching only works once the previous values were erased
+                0x1 if byte == 0xAA && self.accelerometer_erased => {
+                    self.accelerometer_x = MBC7::accelerometer_value(self.tilt.0);
+                    self.accelerometer_y = MBC7::accelerometer_value(self.tilt.1);
+                    self.accelerometer_erased = false;
+                }
+                0x8 => self.eeprom.write(byte),
+                _ => {}
+            },
+            0xA000..=0xBFFF => {}
+            _ => panic!("Invalid MBC7 address: {:#06X}", address),
+        }
+    }
+
+    fn export_ram(&self) -> Vec<u8> {
+        self.eeprom
+            .data
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    fn import_ram(&mut self, data: &[u8]) {
+        for (word, bytes) in self.eeprom.data.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::ROM_BANK_SIZE;
+
+    const EEPROM_REGISTER: u16 = 0xA080;
+
+    fn enabled_mbc7() -> MBC7 {
+        let mut mbc = MBC7::new(vec![0; 2 * ROM_BANK_SIZE]);
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x40);
+        mbc
+    }
+
+    /// Clock a bit into the EEPROM, returning DO after the rising edge
+    fn clock_bit(mbc: &mut MBC7, bit: bool) -> bool {
+        let di = (bit as u8) << EEPROM_DI;
+        mbc.write_byte(EEPROM_REGISTER, 0x80 | di);
+        mbc.write_byte(EEPROM_REGISTER, 0xC0 | di);
+        is_set(mbc.read_byte(EEPROM_REGISTER), 0)
+    }
+
+    fn send_command(mbc: &mut MBC7, command: u16) {
+        // start bit, then 2 opcode bits and 8 address bits
+        clock_bit(mbc, true);
+        for i in (0..10).rev() {
+            clock_bit(mbc, (command >> i) & 1 == 1);
+        }
+    }
+
+    fn deselect(mbc: &mut MBC7) {
+        mbc.write_byte(EEPROM_REGISTER, 0x00);
+    }
+
+    fn read_word(mbc: &mut MBC7, address: u8) -> u16 {
+        send_command(mbc, 0b10 << 8 | address as u16);
+        let word = (0..16).fold(0, |word, _| word << 1 | clock_bit(mbc, false) as u16);
+        deselect(mbc);
+        word
+    }
+
+    fn write_word(mbc: &mut MBC7, address: u8, word: u16) {
+        send_command(mbc, 0b01 << 8 | address as u16);
+        for i in (0..16).rev() {
+            clock_bit(mbc, (word >> i) & 1 == 1);
+        }
+        deselect(mbc);
+    }
+
+    #[test]
+    fn test_eeprom_write_protect() {
+        let mut mbc = enabled_mbc7();
+        write_word(&mut mbc, 0x12, 0xBEEF);
+        assert_eq!(read_word(&mut mbc, 0x12), 0xFFFF);
+
+        // EWEN
+        send_command(&mut mbc, 0b00_11 << 6);
+        deselect(&mut mbc);
+        write_word(&mut mbc, 0x12, 0xBEEF);
+        assert_eq!(read_word(&mut mbc, 0x12), 0xBEEF);
+        assert_eq!(mbc.export_ram()[0x24..0x26], [0xEF, 0xBE]);
+
+        // ERASE
+        send_command(&mut mbc, 0b11 << 8 | 0x12);
+        deselect(&mut mbc);
+        assert_eq!(read_word(&mut mbc, 0x12), 0xFFFF);
+    }
+
+    #[test]
+    fn test_eeprom_write_all() {
+        let mut mbc = enabled_mbc7();
+        send_command(&mut mbc, 0b00_11 << 6);
+        deselect(&mut mbc);
+
+        // WRAL
+        send_command(&mut mbc, 0b00_01 << 6);
+        for i in (0..16).rev() {
+            clock_bit(&mut mbc, (0x1234 >> i) & 1 == 1);
+        }
+        deselect(&mut mbc);
+        assert_eq!(read_word(&mut mbc, 0x00), 0x1234);
+        assert_eq!(read_word(&mut mbc, 0x7F), 0x1234);
+    }
+
+    #[test]
+    fn test_accelerometer_latch() {
+        let mut mbc = enabled_mbc7();
+        mbc.set_tilt(1.0, -0.5);
+
+        // latching without erasing first does nothing
+        mbc.write_byte(0xA010, 0xAA);
+        assert_eq!(mbc.read_byte(0xA030), 0x80);
+
+        mbc.write_byte(0xA000, 0x55);
+        mbc.write_byte(0xA010, 0xAA);
+        let x = mbc.read_byte(0xA020) as u16 | (mbc.read_byte(0xA030) as u16) << 8;
+        let y = mbc.read_byte(0xA040) as u16 | (mbc.read_byte(0xA050) as u16) << 8;
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x38);
+    }
+}
@@ -0,0 +1,459 @@
+use std::{error::Error, fmt};
+
+use crate::cartridge::ROM_BANK_SIZE;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// The largest ROM a cartridge header can declare, 8 MiB
+const MAX_TARGET_SIZE: usize = (2 * ROM_BANK_SIZE) << 8;
+
+// UPS and BPS patches end with the CRC32 of the source, the target and the patch itself
+const FOOTER_SIZE: usize = 12;
+
+/// File extensions of the patch formats, in the order they are looked for next to a ROM
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    UnknownFormat,
+    // the patch ended in the middle of a record
+    Truncated,
+    // a number, offset or length doesn't fit in memory
+    Overflow,
+    // the patch was made for a ROM of a different size
+    SourceSizeMismatch { expected: usize, actual: usize },
+    // which of the source, target or patch failed its CRC32 check
+    ChecksumMismatch(&'static str),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "Not an IPS, UPS or BPS patch"),
+            PatchError::Truncated => write!(f, "Patch is truncated"),
+            PatchError::Overflow => write!(f, "Patch offset or length is out of range"),
+            PatchError::SourceSizeMismatch { expected, actual } => write!(
+                f,
+                "Patch expects a {} byte ROM, but the ROM is {} bytes",
+                expected, actual
+            ),
+            PatchError::ChecksumMismatch(checksum) => {
+                write!(f, "Patch {} checksum doesn't match", checksum)
+            }
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+/// Apply an IPS, UPS or BPS patch to a ROM, detecting the format from its header
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+/// CRC-32 as used by zip, gzip and PNG
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        PatchReader { data, position }
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.saturating_add(count))
+            .ok_or(PatchError::Truncated)?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, count: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(count)?
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as usize))
+    }
+
+    /// Variable length number used by UPS and BPS. Each byte holds 7 bits,
+    /// and every byte after the first also adds one so there's only one encoding.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.byte()?;
+            value = ((byte & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or(PatchError::Overflow)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Overflow)?;
+            value = value.checked_add(shift).ok_or(PatchError::Overflow)?;
+        }
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = rom.to_vec();
+    let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+
+    loop {
+        let record = reader.bytes(3)?;
+        if record == IPS_EOF {
+            break;
+        }
+        let offset = record
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as usize);
+
+        let size = reader.big_endian(2)?;
+        // a size of 0 is a run of the same byte
+        let data = if size == 0 {
+            let length = reader.big_endian(2)?;
+            vec![reader.byte()?; length]
+        } else {
+            reader.bytes(size)?.to_vec()
+        };
+
+        if target.len() < offset + data.len() {
+            target.resize(offset + data.len(), 0);
+        }
+        target[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // some patches also truncate the ROM
+    if let Ok(length) = reader.big_endian(3) {
+        target.truncate(length);
+    }
+
+    Ok(target)
+}
+
+/// Check the CRC32 footer of a UPS or BPS patch, returning where the footer starts
+fn check_footer(rom: &[u8], patch: &[u8]) -> Result<usize, PatchError> {
+    let footer = patch
+        .len()
+        .checked_sub(FOOTER_SIZE)
+        .ok_or(PatchError::Truncated)?;
+    let checksum = |i: usize| {
+        let start = footer + i * 4;
+        u32::from_le_bytes(patch[start..start + 4].try_into().unwrap())
+    };
+
+    if crc32(&patch[..footer + 8]) != checksum(2) {
+        return Err(PatchError::ChecksumMismatch("patch"));
+    }
+    if crc32(rom) != checksum(0) {
+        return Err(PatchError::ChecksumMismatch("source"));
+    }
+    Ok(footer)
+}
+
+fn check_target(target: &[u8], patch: &[u8], footer: usize) -> Result<(), PatchError> {
+    let expected = u32::from_le_bytes(patch[footer + 4..footer + 8].try_into().unwrap());
+    if crc32(target) != expected {
+        return Err(PatchError::ChecksumMismatch("target"));
+    }
+    Ok(())
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = check_footer(rom, patch)?;
+    let mut reader = PatchReader::new(&patch[..footer], UPS_MAGIC.len());
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::Overflow);
+    }
+    if source_size != rom.len() {
+        return Err(PatchError::SourceSizeMismatch {
+            expected: source_size,
+            actual: rom.len(),
+        });
+    }
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+
+    // hunks of bytes to XOR with the source, each after skipping some unchanged bytes
+    let mut position = 0usize;
+    while reader.position < footer {
+        position = position.saturating_add(reader.number()?);
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                position = position.saturating_add(1);
+                break;
+            }
+            if let Some(target) = target.get_mut(position) {
+                *target ^= byte;
+            }
+            position = position.saturating_add(1);
+        }
+    }
+
+    check_target(&target, patch, footer)?;
+    Ok(target)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = check_footer(rom, patch)?;
+    let mut reader = PatchReader::new(&patch[..footer], BPS_MAGIC.len());
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::Overflow);
+    }
+    if source_size != rom.len() {
+        return Err(PatchError::SourceSizeMismatch {
+            expected: source_size,
+            actual: rom.len(),
+        });
+    }
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    let mut target = Vec::new();
+    let mut source_offset = 0isize;
+    let mut target_offset = 0isize;
+
+    // relative offsets store their sign in the lowest bit
+    let relative = |reader: &mut PatchReader| -> Result<isize, PatchError> {
+        let offset = reader.number()?;
+        let magnitude = (offset >> 1) as isize;
+        Ok(if offset & 1 == 1 {
+            -magnitude
+        } else {
+            magnitude
+        })
+    };
+
+    let advance =
+        |offset: isize, delta: isize| offset.checked_add(delta).ok_or(PatchError::Overflow);
+    let range = |start: isize, length: usize| {
+        let start = usize::try_from(start).map_err(|_| PatchError::Truncated)?;
+        Ok(start..start.checked_add(length).ok_or(PatchError::Overflow)?)
+    };
+
+    while reader.position < footer {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        // actions can't write past the target size
+        if length > target_size.saturating_sub(target.len()) {
+            return Err(PatchError::Overflow);
+        }
+
+        match action & 0x03 {
+            // SourceRead, copy from the same position in the source
+            0 => {
+                let start = target.len();
+                let bytes = rom
+                    .get(start..start + length)
+                    .ok_or(PatchError::Truncated)?;
+                target.extend_from_slice(bytes);
+            }
+            // TargetRead, copy from the patch
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // SourceCopy, copy from anywhere in the source
+            2 => {
+                source_offset = advance(source_offset, relative(&mut reader)?)?;
+                let bytes = rom
+                    .get(range(source_offset, length)?)
+                    .ok_or(PatchError::Truncated)?;
+                target.extend_from_slice(bytes);
+                let length = isize::try_from(length).map_err(|_| PatchError::Overflow)?;
+                source_offset = advance(source_offset, length)?;
+            }
+            // TargetCopy, copy from earlier in the target, which can overlap what's being written
+            _ => {
+                target_offset = advance(target_offset, relative(&mut reader)?)?;
+                for _ in 0..length {
+                    let start =
+                        usize::try_from(target_offset).map_err(|_| PatchError::Truncated)?;
+                    let byte = *target.get(start).ok_or(PatchError::Truncated)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+    check_target(&target, patch, footer)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode_number(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte | 0x80);
+                return bytes;
+            }
+            bytes.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_number_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0x123456] {
+            let encoded = encode_number(value);
+            assert_eq!(PatchReader::new(&encoded, 0).number(), Ok(value));
+        }
+
+        let too_long = [[0x7F; 10].as_slice(), &[0x80]].concat();
+        assert_eq!(
+            PatchReader::new(&too_long, 0).number(),
+            Err(PatchError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_ips() {
+        let rom = [0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        // 2 bytes at 0x000002
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);
+        // a run of 4 0xCC at 0x000006, past the end of the ROM
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+        patch.extend_from_slice(IPS_EOF);
+
+        assert_eq!(
+            apply_patch(&rom, &patch).unwrap(),
+            [0, 0, 0xAA, 0xBB, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]
+        );
+
+        patch.truncate(patch.len() - 4);
+        assert_eq!(apply_patch(&rom, &patch), Err(PatchError::Truncated));
+    }
+
+    #[test]
+    fn test_ups() {
+        let source = b"Hello, world".to_vec();
+        let target = b"Hello, WORLD!".to_vec();
+
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(target.len()));
+        // skip "Hello, ", then XOR the rest
+        patch.extend(encode_number(7));
+        patch.extend([
+            b'w' ^ b'W',
+            b'o' ^ b'O',
+            b'r' ^ b'R',
+            b'l' ^ b'L',
+            b'd' ^ b'D',
+            b'!',
+            0,
+        ]);
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+        assert_eq!(
+            apply_patch(b"Hello, World", &patch),
+            Err(PatchError::ChecksumMismatch("source"))
+        );
+
+        let mut huge = UPS_MAGIC.to_vec();
+        huge.extend(encode_number(source.len()));
+        huge.extend(encode_number(MAX_TARGET_SIZE + 1));
+        let huge = with_footer(huge, &source, &target);
+        assert_eq!(apply_patch(&source, &huge), Err(PatchError::Overflow));
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"abcdef".to_vec();
+        let target = b"abcXYXYXdef".to_vec();
+
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(target.len()));
+        patch.extend(encode_number(0));
+        // SourceRead "abc"
+        patch.extend(encode_number((3 - 1) << 2));
+        // TargetRead "XY"
+        patch.extend(encode_number(((2 - 1) << 2) | 1));
+        patch.extend(b"XY");
+        // TargetCopy "XYX" from offset 3, overlapping itself
+        patch.extend(encode_number(((3 - 1) << 2) | 3));
+        patch.extend(encode_number(3 << 1));
+        // SourceCopy "def" from offset 3
+        patch.extend(encode_number(((3 - 1) << 2) | 2));
+        patch.extend(encode_number(3 << 1));
+        let mut patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+
+        // a TargetCopy longer than the address space
+        let mut overflow = BPS_MAGIC.to_vec();
+        overflow.extend(encode_number(source.len()));
+        overflow.extend(encode_number(target.len()));
+        overflow.extend(encode_number(0));
+        overflow.extend(encode_number(usize::MAX));
+        overflow.extend(encode_number(0));
+        let overflow = with_footer(overflow, &source, &target);
+        assert_eq!(apply_patch(&source, &overflow), Err(PatchError::Overflow));
+
+        patch[5] ^= 0xFF;
+        assert_eq!(
+            apply_patch(&source, &patch),
+            Err(PatchError::ChecksumMismatch("patch"))
+        );
+    }
+}
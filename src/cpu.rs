@@ -3,6 +3,9 @@ use paste::paste;
 
 pub type Cycles = usize;
 
+// T-cycles per second at normal speed
+pub const CPU_CLOCK_SPEED: Cycles = 4_194_304;
+
 pub struct CPU {
     pub registers: Registers,
     ime: bool, // ime flag
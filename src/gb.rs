@@ -1,5 +1,6 @@
 use crate::{
-    cartridge::Cartridge,
+    boot::{BootRom, Model, compat_palettes::CompatPalette},
+    cartridge::{Cartridge, image_source::ImageSource},
     cpu::{CPU, Cycles},
     mmu::{InterruptFlag, MMU},
     utils::{is_set, reset_bit},
@@ -34,12 +35,37 @@ pub struct GameBoy {
 
 impl GameBoy {
     pub fn new(cartridge: Cartridge, print_serial: bool) -> Self {
+        GameBoy::new_with_model(cartridge, print_serial, Model::DMG)
+    }
+
+    /// Skip the boot ROM, starting from the state it leaves `model` in
+    pub fn new_with_model(cartridge: Cartridge, print_serial: bool, model: Model) -> Self {
+        let mut cpu = CPU::new();
+        cpu.registers = model.post_boot_registers(&cartridge.header);
+
         GameBoy {
-            cpu: CPU::new(),
-            mmu: MMU::new(cartridge, print_serial),
+            cpu,
+            mmu: MMU::new_with_model(cartridge, print_serial, model),
         }
     }
 
+    /// Run `boot_rom` before the cartridge, starting from the power on state
+    pub fn new_with_boot_rom(cartridge: Cartridge, print_serial: bool, boot_rom: BootRom) -> Self {
+        let mut cpu = CPU::new();
+        cpu.registers.set_af(0x0000);
+        cpu.registers.set_bc(0x0000);
+        cpu.registers.set_de(0x0000);
+        cpu.registers.set_hl(0x0000);
+        cpu.registers.set_sp(0x0000);
+        cpu.registers.set_pc(0x0000);
+
+        GameBoy {
+            cpu,
+            mmu: MMU::new_with_boot_rom(cartridge, print_serial, boot_rom),
+        }
+    }
+
+    /// Run one instruction, returning how long it took in normal speed cycles
     pub fn tick(&mut self) -> Cycles {
         #[cfg(feature = "gb_doctor")]
         self.print_registers();
@@ -75,15 +101,47 @@ impl GameBoy {
             }
         }
 
+        cycles += self.mmu.take_stall_cycles();
         self.mmu.tick(cycles);
 
-        cycles
+        // in double speed mode, twice as many CPU cycles fit in a frame
+        if self.mmu.is_double_speed() {
+            cycles / 2
+        } else {
+            cycles
+        }
     }
 
     pub fn pixel_data(&self) -> &[u8] {
         self.mmu.ppu.pixel_data()
     }
 
+    /// The Super Game Boy's output, with the border around the colorized screen.
+    /// Only available when running an SGB game on the SGB model.
+    pub fn sgb_pixel_data(&self) -> Option<&[u8]> {
+        self.mmu.sgb.as_ref().map(|sgb| sgb.pixel_data())
+    }
+
+    /// Start buffering stereo audio at the given sample rate. A rate of 0 disables audio output.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mmu.apu.set_sample_rate(sample_rate);
+    }
+
+    /// Take the buffered audio as interleaved left/right samples between -1.0 and 1.0
+    pub fn drain_audio_f32(&mut self) -> Vec<f32> {
+        self.mmu.apu.drain_samples()
+    }
+
+    /// Take the buffered audio as interleaved left/right signed 16 bit samples
+    pub fn drain_audio_i16(&mut self) -> Vec<i16> {
+        self.mmu
+            .apu
+            .drain_samples()
+            .into_iter()
+            .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect()
+    }
+
     fn stack_push_word(&mut self, value: u16) -> Cycles {
         let low = value & 0x00FF;
         let high = value >> 8;
@@ -152,6 +210,28 @@ impl GameBoy {
         );
     }
 
+    /// Whether the cartridge's rumble motor is on, so frontends can vibrate a controller
+    pub fn is_rumbling(&self) -> bool {
+        self.mmu.cartridge.mbc.rumble()
+    }
+
+    /// Tilt the cartridge, for games with an accelerometer.
+    /// Positive x tilts to the right and positive y tilts towards the player, in g.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mmu.cartridge.mbc.set_tilt(x, y);
+    }
+
+    /// Colorize a DMG game running on a CGB with `palette`, instead of the one
+    /// picked from its header. Has no effect on other games.
+    pub fn set_compat_palette(&mut self, palette: CompatPalette) {
+        self.mmu.ppu.set_compat_palette(&palette);
+    }
+
+    /// Replace the picture the Pocket Camera sees, which is a test pattern by default
+    pub fn set_camera_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mmu.cartridge.mbc.set_image_source(source);
+    }
+
     pub fn on_button_press(&mut self, button: GBButton) {
         self.mmu.joypad.on_button_press(button);
     }
@@ -0,0 +1,122 @@
+use crate::utils::is_set;
+
+// Transfers are done in blocks of 16 bytes
+pub const HDMA_BLOCK_SIZE: u16 = 0x10;
+// 8 M-cycles at normal speed, each block takes the same time at double speed
+pub const HDMA_BLOCK_CYCLES: usize = 32;
+
+const VRAM_BASE_ADDRESS: u16 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HdmaMode {
+    // everything at once, with the CPU stalled
+    General,
+    // one block at the start of each HBlank
+    HBlank,
+}
+
+/// CGB VRAM DMA (HDMA1 - HDMA5). The MMU copies the blocks this hands out.
+pub struct Hdma {
+    source: u16,
+    // offset into VRAM
+    destination: u16,
+    // blocks left minus one, as HDMA5 reports it
+    remaining: u8,
+    mode: Option<HdmaMode>,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            destination: 0,
+            remaining: 0x7F,
+            mode: None,
+        }
+    }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            // the address registers are write only
+            0xFF51..=0xFF54 => 0xFF,
+            // bit 7 is cleared while a transfer is active
+            0xFF55 => ((self.mode.is_none() as u8) << 7) | self.remaining,
+            _ => panic!("Invalid HDMA address {:#06X}", address),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            0xFF51 => self.source = ((byte as u16) << 8) | (self.source & 0x00FF),
+            0xFF52 => self.source = (self.source & 0xFF00) | (byte & 0xF0) as u16,
+            0xFF53 => {
+                self.destination = (((byte & 0x1F) as u16) << 8) | (self.destination & 0x00FF)
+            }
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (byte & 0xF0) as u16,
+            0xFF55 => match self.mode {
+                // writing 0 to bit 7 cancels an HBlank transfer
+                Some(HdmaMode::HBlank) if !is_set(byte, 7) => self.mode = None,
+                _ => {
+                    self.remaining = byte & 0x7F;
+                    self.mode = Some(if is_set(byte, 7) {
+                        HdmaMode::HBlank
+                    } else {
+                        HdmaMode::General
+                    });
+                }
+            },
+            _ => panic!("Invalid HDMA address {:#06X}", address),
+        }
+    }
+
+    pub fn mode(&self) -> Option<HdmaMode> {
+        self.mode
+    }
+
+    /// Source and VRAM destination addresses of the next block, if a transfer is active
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        self.mode?;
+
+        let block = (self.source, VRAM_BASE_ADDRESS + self.destination);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_SIZE);
+        self.destination = (self.destination + HDMA_BLOCK_SIZE) & 0x1FF0;
+
+        if self.remaining == 0 {
+            self.mode = None;
+            self.remaining = 0x7F;
+        } else {
+            self.remaining -= 1;
+        }
+
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hblank_transfer() {
+        let mut hdma = Hdma::new();
+        assert_eq!(hdma.read_byte(0xFF55), 0xFF);
+
+        hdma.write_byte(0xFF51, 0xC1);
+        hdma.write_byte(0xFF52, 0x2F);
+        hdma.write_byte(0xFF53, 0xFF);
+        hdma.write_byte(0xFF54, 0xF0);
+        hdma.write_byte(0xFF55, 0x82);
+        assert_eq!(hdma.read_byte(0xFF55), 0x02);
+
+        // destinations wrap around within VRAM
+        assert_eq!(hdma.next_block(), Some((0xC120, 0x9FF0)));
+        assert_eq!(hdma.next_block(), Some((0xC130, 0x8000)));
+        assert_eq!(hdma.read_byte(0xFF55), 0x00);
+
+        // cancelling leaves the remaining length readable
+        hdma.write_byte(0xFF55, 0x00);
+        assert_eq!(hdma.mode(), None);
+        assert_eq!(hdma.read_byte(0xFF55), 0x80);
+        assert_eq!(hdma.next_block(), None);
+    }
+}
@@ -13,6 +13,10 @@ pub struct Joypad {
     dpad: u8,
     buttons: u8,
 
+    // Super Game Boy multiplayer (MLT_REQ). Only player 1 has any buttons.
+    players: u8,
+    player: u8,
+
     interrupt_flag: Rc<RefCell<u8>>,
 }
 
@@ -24,11 +28,23 @@ impl Joypad {
             dpad: 0xFF,
             buttons: 0xFF,
 
+            players: 1,
+            player: 0,
+
             interrupt_flag,
         }
     }
 
     pub fn read(&self) -> u8 {
+        if self.player != 0 {
+            // other players never press anything
+            return match (self.select_dpad, self.select_buttons) {
+                (true, _) => 0xEF,
+                (false, true) => 0xDF,
+                (false, false) => 0xFF - self.player,
+            };
+        }
+
         if self.select_dpad {
             0xE0 | (self.dpad & 0x0F)
         } else if self.select_buttons {
@@ -39,10 +55,20 @@ impl Joypad {
     }
 
     pub fn write(&mut self, byte: u8) {
+        // the next player is read from once P15 is released
+        if self.select_buttons && is_set(byte, 5) {
+            self.player = (self.player + 1) % self.players;
+        }
+
         self.select_buttons = !is_set(byte, 5);
         self.select_dpad = !is_set(byte, 4);
     }
 
+    pub(crate) fn set_players(&mut self, players: u8) {
+        self.players = players;
+        self.player = 0;
+    }
+
     pub fn on_button_press(&mut self, button: GBButton) {
         match button {
             GBButton::Dpad(joypad_dpad) => self.dpad = reset_bit(self.dpad, joypad_dpad as u8),
@@ -1,10 +1,14 @@
+pub mod apu;
+pub mod boot;
 pub mod cartridge;
 pub mod cpu;
 pub mod gb;
+mod hdma;
 mod instructions;
 mod joypad;
 pub mod mmu;
 pub mod ppu;
 mod serial;
+pub mod sgb;
 mod timer;
 mod utils;
@@ -1,32 +1,52 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::{
-    cartridge::Cartridge,
+    apu::APU,
+    boot::{BootRom, Model, compat_palettes},
+    cartridge::{Cartridge, header::CGBFlag},
     cpu::Cycles,
+    hdma::{HDMA_BLOCK_CYCLES, HDMA_BLOCK_SIZE, Hdma, HdmaMode},
     joypad::Joypad,
     ppu::{OAM_BASE_ADDRESS, OAM_SIZE, PPU},
     serial::Serial,
+    sgb::Sgb,
     timer::Timer,
-    utils::compose_bytes,
+    utils::{compose_bytes, is_set},
 };
 
-const WRAM_SIZE: usize = 0xE000 - 0xC000;
+const WRAM_BANK_SIZE: usize = 0x1000;
+// only banks 0 and 1 are used outside CGB mode
+const WRAM_BANKS: usize = 8;
 const HRAM_SIZE: usize = 0xFFFF - 0xFF80;
 
 pub struct MMU {
-    wram: [u8; WRAM_SIZE],
+    wram: [[u8; WRAM_BANK_SIZE]; WRAM_BANKS],
+    wram_bank: usize, // SVBK, bank mapped at 0xD000 - 0xDFFF
     hram: [u8; HRAM_SIZE],
     dma: u8, // OAM DMA source address & start
+    // mapped over the cartridge until 0xFF50 is written
+    boot_rom: Option<BootRom>,
+
+    // CGB registers are only mapped in CGB mode, not for DMG games on a CGB
+    cgb_mode: bool,
+    // KEY1
+    double_speed: bool,
+    speed_switch_armed: bool,
+    hdma: Hdma,
+    // CPU cycles to stall for, while VRAM DMA runs
+    stall_cycles: Cycles,
 
-    stub_audio: [u8; 0xFF26 - 0xFF10 + 1], // TODO: remove this when implemented audio
     pub interrupt_enable: u8,
     pub interrupt_flag: Rc<RefCell<u8>>,
 
     pub ppu: PPU,
+    pub apu: APU,
     pub joypad: Joypad,
     pub timer: Timer,
     pub cartridge: Cartridge,
     pub serial: Serial,
+    // only with the SGB model, for games that support it
+    pub sgb: Option<Sgb>,
 
     #[cfg(feature = "test")]
     test_ram: [u8; 0xFFFF + 1],
@@ -57,16 +77,29 @@ impl InterruptFlag {
 
 impl MMU {
     pub fn new(cartridge: Cartridge, print_serial: bool) -> Self {
+        MMU::new_with_model(cartridge, print_serial, Model::DMG)
+    }
+
+    /// Games without CGB support run in compatibility mode on a CGB
+    pub fn new_with_model(cartridge: Cartridge, print_serial: bool, model: Model) -> Self {
         let interrupt_flag = Rc::new(RefCell::new(0xE1));
-        MMU {
-            wram: [0; WRAM_SIZE],
+        let mut mmu = MMU {
+            wram: [[0; WRAM_BANK_SIZE]; WRAM_BANKS],
+            wram_bank: 1,
             hram: [0; HRAM_SIZE],
             dma: 0xFF,
-            stub_audio: [0; 0xFF26 - 0xFF10 + 1],
+            boot_rom: None,
+            cgb_mode: false,
+            double_speed: false,
+            speed_switch_armed: false,
+            hdma: Hdma::new(),
+            stall_cycles: 0,
             ppu: PPU::new(interrupt_flag.clone()),
+            apu: APU::new(),
             joypad: Joypad::new(interrupt_flag.clone()),
             timer: Timer::new(interrupt_flag.clone()),
             serial: Serial::new(print_serial),
+            sgb: None,
             cartridge,
 
             interrupt_enable: 0,
@@ -74,13 +107,52 @@ impl MMU {
 
             #[cfg(feature = "test")]
             test_ram: [0; 0xFFFF + 1],
+        };
+        mmu.timer.set_div(model.post_boot_div());
+        let cgb_game = mmu.cartridge.header.cgb_flag != CGBFlag::DMGOnly;
+        mmu.set_cgb_mode(model == Model::CGB && cgb_game);
+        // the SGB ignores games without the SGB flag, or with an old licensee code
+        let header = &mmu.cartridge.header;
+        if model == Model::SGB && header.sgb_flag && header.old_licensee_code == 0x33 {
+            mmu.sgb = Some(Sgb::new());
+        }
+        if model == Model::CGB && !cgb_game {
+            let palette = compat_palettes::header_palette(&mmu.cartridge.header);
+            mmu.ppu.set_compat_palette(&palette);
+            mmu.ppu.set_dmg_compat(true);
         }
+        mmu
+    }
+
+    /// Start from the power on state, with `boot_rom` left to set everything up
+    pub fn new_with_boot_rom(cartridge: Cartridge, print_serial: bool, boot_rom: BootRom) -> Self {
+        let model = boot_rom.model();
+        let mut mmu = MMU::new_with_model(cartridge, print_serial, model);
+        // the CGB boot ROM switches to compatibility mode itself, through KEY0
+        mmu.set_cgb_mode(model == Model::CGB);
+        mmu.boot_rom = Some(boot_rom);
+
+        *mmu.interrupt_flag.borrow_mut() = 0xE0;
+        mmu.write_byte(0xFF40, 0x00);
+        mmu.write_byte(0xFF47, 0x00);
+        // writing DIV resets it
+        mmu.write_byte(0xFF04, 0x00);
+        mmu.write_byte(0xFF26, 0x00);
+        mmu
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
         #[cfg(feature = "test")]
         return self.test_ram[address as usize];
 
+        if let Some(byte) = self
+            .boot_rom
+            .as_ref()
+            .and_then(|boot_rom| boot_rom.read_byte(address))
+        {
+            return byte;
+        }
+
         match address {
             // cartridge
             0x0000..=0x7FFF => self.cartridge.mbc.read_byte(address),
@@ -89,7 +161,8 @@ impl MMU {
             // External RAM (from cartridge)
             0xA000..=0xBFFF => self.cartridge.mbc.read_byte(address),
             // WRAM
-            0xC000..=0xDFFF => self.wram[(address - 0xC000) as usize],
+            0xC000..=0xCFFF => self.wram[0][(address - 0xC000) as usize],
+            0xD000..=0xDFFF => self.wram[self.wram_bank][(address - 0xD000) as usize],
             // Echo RAM (prohibited)
             0xE000..=0xFDFF => self.read_byte(address - 0x2000),
             // OAM (Object attribute memory)
@@ -101,11 +174,24 @@ impl MMU {
             // LCD control and flags
             0xFF46 => self.dma,
             0xFF40..=0xFF4B => self.ppu.read_byte(address),
+            // Speed switch (KEY1)
+            0xFF4D if self.cgb_mode => {
+                0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8
+            }
+            // VRAM bank select (VBK)
+            0xFF4F if self.cgb_mode => self.ppu.read_byte(address),
+            // CGB palettes (BCPS/BCPD, OCPS/OCPD)
+            0xFF68..=0xFF6B if self.cgb_mode => self.ppu.read_byte(address),
+            // VRAM DMA (HDMA1 - HDMA5)
+            0xFF51..=0xFF55 if self.cgb_mode => self.hdma.read_byte(address),
+            // WRAM bank select (SVBK)
+            0xFF70 if self.cgb_mode => 0xF8 | self.wram_bank as u8,
             // I/O Registers
             0xFF00 => self.joypad.read(),
             0xFF01..=0xFF02 => self.serial.read_byte(address),
             0xFF04..=0xFF07 => self.timer.read_byte(address),
-            0xFF10..=0xFF26 => self.stub_audio[(address - 0xFF10) as usize],
+            // Audio
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.read_byte(address),
             // HRAM (high RAM)
             0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize],
             // Interrupt Enable register (IE)
@@ -132,7 +218,8 @@ impl MMU {
             // External RAM (from cartridge)
             0xA000..=0xBFFF => self.cartridge.mbc.write_byte(address, byte),
             // WRAM
-            0xC000..=0xDFFF => self.wram[(address - 0xC000) as usize] = byte,
+            0xC000..=0xCFFF => self.wram[0][(address - 0xC000) as usize] = byte,
+            0xD000..=0xDFFF => self.wram[self.wram_bank][(address - 0xD000) as usize] = byte,
             // Echo RAM (prohibited)
             0xE000..=0xFDFF => self.write_byte(address - 0x2000, byte),
             // OAM (Object attribute memory)
@@ -152,11 +239,45 @@ impl MMU {
             0xFF40..=0xFF4B => self.ppu.write_byte(address, byte),
             // Interrupt flag (IF)
             0xFF0F => *self.interrupt_flag.borrow_mut() = byte,
+            // Boot ROM disable, can't be mapped back in
+            0xFF50 if byte != 0 => self.boot_rom = None,
+            // CGB mode select (KEY0), only writable by the boot ROM
+            // the boot ROM sets up the compatibility palettes itself
+            0xFF4C if self.boot_rom.is_some() => {
+                let dmg_compat = is_set(byte, 2);
+                self.set_cgb_mode(!dmg_compat);
+                self.ppu.set_dmg_compat(dmg_compat);
+            }
+            0xFF4D if self.cgb_mode => self.speed_switch_armed = is_set(byte, 0),
+            0xFF4F if self.cgb_mode => self.ppu.write_byte(address, byte),
+            0xFF68..=0xFF6B if self.cgb_mode => self.ppu.write_byte(address, byte),
+            0xFF51..=0xFF55 if self.cgb_mode => {
+                self.hdma.write_byte(address, byte);
+                match self.hdma.mode() {
+                    Some(HdmaMode::General) => while self.transfer_hdma_block() {},
+                    // the first block is copied straight away if there's no HBlank to wait for
+                    Some(HdmaMode::HBlank) if address == 0xFF55 && self.ppu.in_hblank() => {
+                        self.transfer_hdma_block();
+                    }
+                    _ => {}
+                }
+            }
+            // bank 0 selects bank 1
+            0xFF70 if self.cgb_mode => self.wram_bank = (byte as usize & 0x07).max(1),
             // I/O Registers
-            0xFF00 => self.joypad.write(byte),
+            0xFF00 => {
+                self.joypad.write(byte);
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_joypad(byte);
+                    if let Some(players) = sgb.take_player_count() {
+                        self.joypad.set_players(players);
+                    }
+                }
+            }
             0xFF01..=0xFF02 => self.serial.write_byte(address, byte),
             0xFF04..=0xFF07 => self.timer.write_byte(address, byte),
-            0xFF10..=0xFF26 => self.stub_audio[(address - 0xFF10) as usize] = byte,
+            // Audio
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.write_byte(address, byte),
             // HRAM (high RAM)
             0xFF80..=0xFFFE => self.hram[(address - 0xFF80) as usize] = byte,
             // Interrupt Enable register (IE)
@@ -172,8 +293,225 @@ impl MMU {
         self.write_byte(address.wrapping_add(1), high as u8);
     }
 
+    fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb_mode = enabled;
+        self.ppu.set_cgb_mode(enabled);
+    }
+
+    /// Copy the next VRAM DMA block, returning false once there is none left
+    fn transfer_hdma_block(&mut self) -> bool {
+        let Some((source, destination)) = self.hdma.next_block() else {
+            return false;
+        };
+
+        for i in 0..HDMA_BLOCK_SIZE {
+            let byte = self.read_byte(source.wrapping_add(i));
+            self.write_byte(destination + i, byte);
+        }
+        // the CPU runs twice as many cycles per block in double speed mode
+        self.stall_cycles += HDMA_BLOCK_CYCLES << self.double_speed as usize;
+        true
+    }
+
+    /// Take the cycles the CPU has to wait for VRAM DMA
+    pub fn take_stall_cycles(&mut self) -> Cycles {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Called on STOP, which switches speed if it was requested through KEY1
+    pub fn switch_speed(&mut self) {
+        if !self.speed_switch_armed {
+            return;
+        }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        // STOP resets DIV
+        self.timer.write_byte(0xFF04, 0x00);
+    }
+
+    /// `cycles` are CPU cycles, which only the timer keeps counting in double speed mode
     pub fn tick(&mut self, cycles: Cycles) {
-        self.ppu.tick(cycles);
+        let real_cycles = if self.double_speed {
+            cycles / 2
+        } else {
+            cycles
+        };
+
+        self.ppu.tick(real_cycles);
+        if self.ppu.take_vblank()
+            && let Some(sgb) = &mut self.sgb
+        {
+            sgb.on_frame(self.ppu.shade_data());
+        }
+        if self.ppu.take_hblank() && self.hdma.mode() == Some(HdmaMode::HBlank) {
+            self.transfer_hdma_block();
+        }
         self.timer.tick(cycles);
+        self.apu.tick(real_cycles);
+        self.cartridge.mbc.tick(real_cycles);
+    }
+}
+
+// the test feature replaces the memory map with flat RAM
+#[cfg(all(test, not(feature = "test")))]
+mod test {
+    use super::*;
+    use crate::cartridge::{ROM_BANK_SIZE, header::HEADER_END};
+
+    fn cartridge(cgb_flag: u8) -> Cartridge {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[0x0143] = cgb_flag;
+        rom[0x0144..HEADER_END].fill(0);
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    #[test]
+    fn test_wram_banking() {
+        let mut mmu = MMU::new_with_model(cartridge(0x80), false, Model::CGB);
+        mmu.write_byte(0xD000, 0x11);
+        mmu.write_byte(0xFF70, 0x02);
+        assert_eq!(mmu.read_byte(0xFF70), 0xFA);
+        assert_eq!(mmu.read_byte(0xD000), 0x00);
+        mmu.write_byte(0xD000, 0x22);
+        // bank 0 can't be mapped at 0xD000
+        mmu.write_byte(0xFF70, 0x00);
+        assert_eq!(mmu.read_byte(0xD000), 0x11);
+        assert_eq!(mmu.read_byte(0xF000), 0x11);
+
+        // DMG games on a CGB can't switch banks
+        let mut mmu = MMU::new_with_model(cartridge(0x00), false, Model::CGB);
+        mmu.write_byte(0xD000, 0x11);
+        mmu.write_byte(0xFF70, 0x02);
+        assert_eq!(mmu.read_byte(0xFF70), 0xFF);
+        assert_eq!(mmu.read_byte(0xD000), 0x11);
+    }
+
+    #[test]
+    fn test_vram_banking() {
+        let mut mmu = MMU::new_with_model(cartridge(0xC0), false, Model::CGB);
+        mmu.write_byte(0x8000, 0x11);
+        mmu.write_byte(0xFF4F, 0x01);
+        assert_eq!(mmu.read_byte(0xFF4F), 0xFF);
+        assert_eq!(mmu.read_byte(0x8000), 0x00);
+        mmu.write_byte(0x8000, 0x22);
+        mmu.write_byte(0xFF4F, 0x00);
+        assert_eq!(mmu.read_byte(0x8000), 0x11);
+    }
+
+    #[test]
+    fn test_speed_switch() {
+        let mut mmu = MMU::new_with_model(cartridge(0x80), false, Model::CGB);
+        assert_eq!(mmu.read_byte(0xFF4D), 0x7E);
+
+        // STOP without arming the switch does nothing
+        mmu.switch_speed();
+        assert!(!mmu.is_double_speed());
+
+        mmu.write_byte(0xFF4D, 0x01);
+        assert_eq!(mmu.read_byte(0xFF4D), 0x7F);
+        mmu.switch_speed();
+        assert!(mmu.is_double_speed());
+        assert_eq!(mmu.read_byte(0xFF4D), 0xFE);
+        assert_eq!(mmu.read_byte(0xFF04), 0x00);
+
+        // the timer keeps counting CPU cycles, while the PPU only sees half of them
+        for _ in 0..456 / 4 {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.read_byte(0xFF04), 0x01);
+        assert_eq!(mmu.ppu.read_byte(0xFF44), 0x00);
+        for _ in 0..456 / 4 {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.ppu.read_byte(0xFF44), 0x01);
+    }
+
+    #[test]
+    fn test_hdma() {
+        let mut mmu = MMU::new_with_model(cartridge(0x80), false, Model::CGB);
+        for i in 0..0x40 {
+            mmu.write_byte(0xC000 + i, i as u8);
+        }
+        mmu.write_byte(0xFF51, 0xC0);
+        mmu.write_byte(0xFF52, 0x00);
+        mmu.write_byte(0xFF53, 0x00);
+        mmu.write_byte(0xFF54, 0x10);
+
+        // general transfers finish straight away, stalling the CPU
+        mmu.write_byte(0xFF55, 0x01);
+        assert_eq!(mmu.read_byte(0x8010), 0x00);
+        assert_eq!(mmu.read_byte(0x801F), 0x0F);
+        assert_eq!(mmu.read_byte(0x802F), 0x1F);
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+        assert_eq!(mmu.take_stall_cycles(), 2 * HDMA_BLOCK_CYCLES);
+
+        // HBlank transfers copy one block per HBlank
+        mmu.write_byte(0xFF55, 0x81);
+        assert_eq!(mmu.read_byte(0x8030), 0x00);
+        for _ in 0..(80 + 172) / 4 {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.read_byte(0x8030), 0x20);
+        assert_eq!(mmu.read_byte(0x8040), 0x00);
+        assert_eq!(mmu.read_byte(0xFF55), 0x00);
+        assert_eq!(mmu.take_stall_cycles(), HDMA_BLOCK_CYCLES);
+    }
+
+    #[test]
+    fn test_hdma_lcd_off() {
+        let mut mmu = MMU::new_with_model(cartridge(0x80), false, Model::CGB);
+        mmu.write_byte(0xFF40, 0x11);
+        for i in 0..0x20 {
+            mmu.write_byte(0xC000 + i, i as u8 + 1);
+        }
+        mmu.write_byte(0xFF51, 0xC0);
+        mmu.write_byte(0xFF52, 0x00);
+        mmu.write_byte(0xFF53, 0x00);
+        mmu.write_byte(0xFF54, 0x00);
+
+        // only the first block is copied, the rest waits for the LCD
+        mmu.write_byte(0xFF55, 0x81);
+        assert_eq!(mmu.read_byte(0x800F), 0x10);
+        for _ in 0..456 / 4 {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.read_byte(0x8010), 0x00);
+        assert_eq!(mmu.read_byte(0xFF55), 0x00);
+    }
+
+    #[test]
+    fn test_sgb_multiplayer() {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[0x0146] = 0x03;
+        rom[0x014B] = 0x33;
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        let mut mmu = MMU::new_with_model(cartridge, false, Model::SGB);
+        assert!(mmu.sgb.is_some());
+        assert_eq!(mmu.read_byte(0xFF00), 0xFF);
+
+        // MLT_REQ for 2 players, then a stop bit
+        let packet = [0x89, 0x01];
+        mmu.write_byte(0xFF00, 0x00);
+        mmu.write_byte(0xFF00, 0x30);
+        for i in 0..16 * 8 + 1 {
+            let bit = packet
+                .get(i / 8)
+                .is_some_and(|byte| is_set(*byte, (i % 8) as u8));
+            mmu.write_byte(0xFF00, if bit { 0x10 } else { 0x20 });
+            mmu.write_byte(0xFF00, 0x30);
+        }
+        assert_eq!(mmu.read_byte(0xFF00), 0xFF);
+
+        // releasing P15 switches to the next player
+        mmu.write_byte(0xFF00, 0x10);
+        mmu.write_byte(0xFF00, 0x30);
+        assert_eq!(mmu.read_byte(0xFF00), 0xFE);
+        mmu.write_byte(0xFF00, 0x10);
+        mmu.write_byte(0xFF00, 0x30);
+        assert_eq!(mmu.read_byte(0xFF00), 0xFF);
     }
 }
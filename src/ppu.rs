@@ -1,6 +1,7 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::{
+    boot::compat_palettes::CompatPalette,
     cpu::Cycles,
     mmu::InterruptFlag,
     utils::{is_set, reset_bit, set_bit},
@@ -13,14 +14,28 @@ pub const OAM_SIZE: usize = OAM_END_ADDRESS as usize - OAM_BASE_ADDRESS as usize
 const VRAM_BASE_ADDRESS: u16 = 0x8000;
 const VRAM_END_ADDRESS: u16 = 0x9FFF;
 const VRAM_SIZE: usize = VRAM_END_ADDRESS as usize - VRAM_BASE_ADDRESS as usize + 1;
+// the second bank only exists on the CGB
+const VRAM_BANKS: usize = 2;
 
 enum SpriteFlags {
     Priority = 7,
     YFlip = 6,
     XFlip = 5,
     DMGPalette = 4,
+    CGBBank = 3,
 }
 
+// CGB tile attributes, stored in VRAM bank 1 at the same address as the tile index
+enum BgAttributes {
+    Priority = 7,
+    YFlip = 6,
+    XFlip = 5,
+    Bank = 3,
+}
+
+// CGB palette number, in both sprite flags and BG attributes
+const CGB_PALETTE_MASK: u8 = 0x07;
+
 enum LCDCBits {
     LCDEnable = 7,
     WindowTileMap = 6,
@@ -64,7 +79,7 @@ const BYTES_PER_TILE: usize = 16;
 const BYTES_PER_LINE: usize = 2;
 const BYTES_PER_SPRITE: usize = 4;
 
-type Color = [u8; 4]; // RGBA8888 format
+pub(crate) type Color = [u8; 4]; // RGBA8888 format
 type Palette = u8;
 
 const MONOCHROME_PALETTE: [Color; 4] = [
@@ -74,11 +89,110 @@ const MONOCHROME_PALETTE: [Color; 4] = [
     [0x00, 0x00, 0x00, 0xFF], // black
 ];
 
+pub(crate) fn rgb555_to_rgba(color: u16) -> Color {
+    // scale 5 bit channels up to 8 bits
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1F) as u8;
+        (value << 3) | (value >> 2)
+    };
+    [channel(0), channel(5), channel(10), 0xFF]
+}
+
+// 8 palettes of 4 RGB555 colors
+const CGB_PALETTE_RAM_SIZE: usize = 64;
+
+/// CGB palette RAM, accessed through an index register (BCPS/OCPS)
+/// and a data register (BCPD/OCPD)
+struct ColorPalettes {
+    data: [u8; CGB_PALETTE_RAM_SIZE],
+    index: u8,
+    auto_increment: bool,
+}
+
+impl ColorPalettes {
+    fn new() -> Self {
+        ColorPalettes {
+            data: [0xFF; CGB_PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    fn read_spec(&self) -> u8 {
+        0x40 | ((self.auto_increment as u8) << 7) | self.index
+    }
+
+    fn write_spec(&mut self, byte: u8) {
+        self.index = byte & 0x3F;
+        self.auto_increment = is_set(byte, 7);
+    }
+
+    fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    fn write_data(&mut self, byte: u8) {
+        self.data[self.index as usize] = byte;
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    fn set_colors(&mut self, palette: u8, colors: &[u16; 4]) {
+        let base = palette as usize * 8;
+        for (i, color) in colors.iter().enumerate() {
+            self.data[base + i * 2..base + i * 2 + 2].copy_from_slice(&color.to_le_bytes());
+        }
+    }
+
+    fn colors(&self, palette: u8) -> [Color; 4] {
+        let base = palette as usize * 8;
+        [0, 1, 2, 3].map(|i| {
+            rgb555_to_rgba(u16::from_le_bytes([
+                self.data[base + i * 2],
+                self.data[base + i * 2 + 1],
+            ]))
+        })
+    }
+}
+
+/// The colors a tile's color indices map to, along with the DMG shades behind them
+#[derive(Clone, Copy)]
+struct TileColors {
+    colors: [Color; 4],
+    shades: [u8; 4],
+}
+
+impl TileColors {
+    // CGB palettes don't go through DMG shades
+    fn cgb(colors: [Color; 4]) -> Self {
+        TileColors {
+            colors,
+            shades: [0, 1, 2, 3],
+        }
+    }
+}
+
+/// What the background and window left at a pixel, which sprites are drawn against
+#[derive(Clone, Copy, Default)]
+struct BgPixel {
+    color_index: u8,
+    // CGB BG attribute putting the background over sprites
+    priority: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Layer {
+    Background { priority: bool },
+    Sprite { behind_background: bool },
+}
+
 pub struct PPU {
     mode_clock: usize,
     mode: PPUMode,
 
-    vram: [u8; VRAM_SIZE],
+    vram: [[u8; VRAM_SIZE]; VRAM_BANKS],
+    vram_bank: usize, // VBK, bank the CPU sees
     oam: [u8; OAM_SIZE],
 
     lcdc: u8, // LCD control
@@ -95,6 +209,10 @@ pub struct PPU {
     wy: u8,
     wx: u8,
     window_line_counter: u8,
+    // set when a visible line reaches HBlank, for HBlank DMA
+    entered_hblank: bool,
+    // set when a frame is finished
+    entered_vblank: bool,
 
     bgp: Palette, // BG palette data
 
@@ -102,8 +220,19 @@ pub struct PPU {
     obp0: Palette,
     obp1: Palette,
 
+    // CGB palettes, used instead of the ones above in CGB mode
+    bg_palettes: ColorPalettes,
+    obj_palettes: ColorPalettes,
+    cgb_mode: bool,
+    // DMG games on a CGB, colorized through palette RAM
+    dmg_compat: bool,
+
+    bg_line: [BgPixel; GB_SCREEN_WIDTH],
     frame: [Color; GB_SCREEN_HEIGHT * GB_SCREEN_WIDTH],
     display: [Color; GB_SCREEN_HEIGHT * GB_SCREEN_WIDTH],
+    // DMG shade of each pixel, which the Super Game Boy colorizes
+    frame_shades: [u8; GB_SCREEN_HEIGHT * GB_SCREEN_WIDTH],
+    display_shades: [u8; GB_SCREEN_HEIGHT * GB_SCREEN_WIDTH],
 
     palette: [Color; 4],
     interrupt_flag: Rc<RefCell<u8>>,
@@ -115,7 +244,8 @@ impl PPU {
             mode_clock: 0,
             mode: PPUMode::OAM,
             oam: [0; OAM_SIZE],
-            vram: [0; VRAM_SIZE],
+            vram: [[0; VRAM_SIZE]; VRAM_BANKS],
+            vram_bank: 0,
 
             lcdc: 0x91,
 
@@ -129,13 +259,23 @@ impl PPU {
             wy: 0,
             wx: 0,
             window_line_counter: 0,
+            entered_hblank: false,
+            entered_vblank: false,
 
             bgp: 0xFC,
             obp0: 0,
             obp1: 0,
 
+            bg_palettes: ColorPalettes::new(),
+            obj_palettes: ColorPalettes::new(),
+            cgb_mode: false,
+            dmg_compat: false,
+
+            bg_line: [BgPixel::default(); GB_SCREEN_WIDTH],
             frame: [MONOCHROME_PALETTE[0]; GB_SCREEN_HEIGHT * GB_SCREEN_WIDTH],
             display: [MONOCHROME_PALETTE[0]; GB_SCREEN_HEIGHT * GB_SCREEN_WIDTH],
+            frame_shades: [0; GB_SCREEN_HEIGHT * GB_SCREEN_WIDTH],
+            display_shades: [0; GB_SCREEN_HEIGHT * GB_SCREEN_WIDTH],
             palette: MONOCHROME_PALETTE,
             interrupt_flag,
         }
@@ -143,7 +283,7 @@ impl PPU {
 
     pub fn read_byte(&self, address: u16) -> u8 {
         match address {
-            0x8000..=0x9FFF => self.vram[(address - 0x8000) as usize],
+            0x8000..=0x9FFF => self.read_vram(self.vram_bank, address),
             0xFE00..=0xFE9F => self.oam[(address - 0xFE00) as usize],
             0xFF40 => self.lcdc,
             0xFF41 => self.stat,
@@ -160,13 +300,18 @@ impl PPU {
             0xFF49 => self.obp1,
             0xFF4A => self.wy,
             0xFF4B => self.wx,
+            0xFF4F => 0xFE | self.vram_bank as u8,
+            0xFF68 => self.bg_palettes.read_spec(),
+            0xFF69 => self.bg_palettes.read_data(),
+            0xFF6A => self.obj_palettes.read_spec(),
+            0xFF6B => self.obj_palettes.read_data(),
             _ => panic!("Invalid PPU Address: {:#06X}", address),
         }
     }
 
     pub fn write_byte(&mut self, address: u16, byte: u8) {
         match address {
-            0x8000..=0x9FFF => self.vram[(address - 0x8000) as usize] = byte,
+            0x8000..=0x9FFF => self.vram[self.vram_bank][(address - 0x8000) as usize] = byte,
             0xFE00..=0xFE9F => self.oam[(address - 0xFE00) as usize] = byte,
             0xFF40 => {
                 self.lcdc = byte;
@@ -186,10 +331,50 @@ impl PPU {
             0xFF49 => self.obp1 = byte,
             0xFF4A => self.wy = byte,
             0xFF4B => self.wx = byte,
+            0xFF4F => self.vram_bank = byte as usize & 0x01,
+            0xFF68 => self.bg_palettes.write_spec(byte),
+            0xFF69 => self.bg_palettes.write_data(byte),
+            0xFF6A => self.obj_palettes.write_spec(byte),
+            0xFF6B => self.obj_palettes.write_data(byte),
             _ => panic!("Invalid PPU Address: {:#06X}", address),
         }
     }
 
+    /// In CGB mode, colors come from palette RAM and tiles can have attributes
+    pub(crate) fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb_mode = enabled;
+    }
+
+    pub(crate) fn set_dmg_compat(&mut self, enabled: bool) {
+        self.dmg_compat = enabled;
+    }
+
+    /// Load the colors the CGB boot ROM gives DMG games, in compatibility mode
+    pub fn set_compat_palette(&mut self, palette: &CompatPalette) {
+        self.bg_palettes.set_colors(0, &palette.bg);
+        self.obj_palettes.set_colors(0, &palette.obj0);
+        self.obj_palettes.set_colors(1, &palette.obj1);
+    }
+
+    /// Whether the PPU is in HBlank, or not drawing at all with the LCD off
+    pub(crate) fn in_hblank(&self) -> bool {
+        !is_set(self.lcdc, LCDCBits::LCDEnable as u8) || self.mode == PPUMode::HBlank
+    }
+
+    /// Whether HBlank started since the last call
+    pub(crate) fn take_hblank(&mut self) -> bool {
+        std::mem::take(&mut self.entered_hblank)
+    }
+
+    /// Whether a frame was finished since the last call
+    pub(crate) fn take_vblank(&mut self) -> bool {
+        std::mem::take(&mut self.entered_vblank)
+    }
+
+    fn read_vram(&self, bank: usize, address: u16) -> u8 {
+        self.vram[bank][(address - VRAM_BASE_ADDRESS) as usize]
+    }
+
     fn set_ly(&mut self, val: u8) {
         self.ly = val;
 
@@ -221,6 +406,8 @@ impl PPU {
             PPUMode::VRAM => {
                 if self.mode_clock >= VRAM_CYCLE_LENGTH {
                     self.mode_clock %= VRAM_CYCLE_LENGTH;
+                    // the line is done by HBlank, so HBlank DMA only affects the next one
+                    self.draw_scanline();
                     self.change_mode(PPUMode::HBlank);
                 }
             }
@@ -228,8 +415,6 @@ impl PPU {
                 if self.mode_clock >= HBLANK_CYCLE_LENGTH {
                     self.mode_clock %= HBLANK_CYCLE_LENGTH;
 
-                    self.draw_scanline();
-
                     self.set_ly(self.ly + 1);
                     if self.ly as usize == GB_SCREEN_HEIGHT {
                         self.change_mode(PPUMode::VBlank);
@@ -258,11 +443,16 @@ impl PPU {
             self.stat |= new_mode as u8;
         }
         self.mode = new_mode;
+        if new_mode == PPUMode::HBlank {
+            self.entered_hblank = true;
+        }
 
         let flag = *self.interrupt_flag.borrow();
         // Request VBlank interrupt
         if new_mode == PPUMode::VBlank {
             self.display.copy_from_slice(&self.frame);
+            self.display_shades.copy_from_slice(&self.frame_shades);
+            self.entered_vblank = true;
             self.window_line_counter = 0;
             *self.interrupt_flag.borrow_mut() = set_bit(flag, InterruptFlag::VBlank as u8);
         }
@@ -273,6 +463,7 @@ impl PPU {
     }
 
     fn draw_scanline(&mut self) {
+        self.bg_line = [BgPixel::default(); GB_SCREEN_WIDTH];
         self.draw_bg();
         self.draw_window();
         self.draw_sprites();
@@ -326,10 +517,15 @@ impl PPU {
 
                 let priority = is_set(sprite_flags, SpriteFlags::Priority as u8);
 
-                let palette = if is_set(sprite_flags, SpriteFlags::DMGPalette as u8) {
-                    self.obp1
+                let (colors, bank) = if self.cgb_mode {
+                    (
+                        TileColors::cgb(self.obj_palettes.colors(sprite_flags & CGB_PALETTE_MASK)),
+                        is_set(sprite_flags, SpriteFlags::CGBBank as u8) as usize,
+                    )
+                } else if is_set(sprite_flags, SpriteFlags::DMGPalette as u8) {
+                    (self.dmg_colors(self.obp1, &self.obj_palettes, 1), 0)
                 } else {
-                    self.obp0
+                    (self.dmg_colors(self.obp0, &self.obj_palettes, 0), 0)
                 };
 
                 let mut line_within_tile = relative_ly - y;
@@ -341,8 +537,8 @@ impl PPU {
 
                 let address = VRAM_BASE_ADDRESS + tile_offset + line_offset;
 
-                let mut p1 = self.read_byte(address);
-                let mut p2 = self.read_byte(address + 1);
+                let mut p1 = self.read_vram(bank, address);
+                let mut p2 = self.read_vram(bank, address + 1);
 
                 if xflip {
                     p1 = p1.reverse_bits();
@@ -361,15 +557,18 @@ impl PPU {
                         0
                     },
                     (x.min(GB_SCREEN_WIDTH as u8) - x_start) as usize,
-                    palette,
-                    Some(priority),
+                    colors,
+                    Layer::Sprite {
+                        behind_background: priority,
+                    },
                 );
             }
         }
     }
 
     fn draw_bg(&mut self) {
-        if !is_set(self.lcdc, LCDCBits::BgWindowEnable as u8) {
+        // On the CGB, this bit only takes priority away from the background
+        if !self.cgb_mode && !is_set(self.lcdc, LCDCBits::BgWindowEnable as u8) {
             return;
         }
 
@@ -379,13 +578,6 @@ impl PPU {
             0x9800
         };
 
-        let tile_data_flag = is_set(self.lcdc, LCDCBits::BgWindowTiles as u8);
-        let tile_data: u16 = if tile_data_flag {
-            VRAM_BASE_ADDRESS
-        } else {
-            0x9000
-        };
-
         let tile_y = ((self.scy as usize + self.ly as usize) % 256) as usize / BASE_TILE_WIDTH;
         let tile_pixel_offset_y =
             ((self.scy as usize + self.ly as usize) % 256) as u16 % BASE_TILE_WIDTH as u16;
@@ -396,20 +588,8 @@ impl PPU {
             let tile_x = x / BASE_TILE_WIDTH;
 
             let tile_index = tile_y * TILE_MAP_WIDTH + tile_x;
-            let bg_index = self.read_byte(bg_map + tile_index as u16);
-
-            let tile_address = if tile_data_flag {
-                // 8000 method
-                tile_data + bg_index as u16 * BYTES_PER_TILE as u16
-            } else {
-                // 8800 method
-                tile_data.wrapping_add((bg_index as i8 as i16 * BYTES_PER_TILE as i16) as u16)
-            };
-
-            let pixels = PPU::compose_pixels(
-                self.read_byte(tile_address + tile_pixel_offset_y * BYTES_PER_LINE as u16),
-                self.read_byte(tile_address + tile_pixel_offset_y * BYTES_PER_LINE as u16 + 1),
-            );
+            let (pixels, attributes) =
+                self.bg_tile_line(bg_map + tile_index as u16, tile_pixel_offset_y);
 
             let start_x_offset = x % BASE_TILE_WIDTH;
             let pixels_to_draw =
@@ -420,14 +600,51 @@ impl PPU {
                 pixels,
                 start_x_offset,
                 pixels_to_draw,
-                self.bgp,
-                None,
+                self.bg_colors(attributes),
+                Layer::Background {
+                    priority: is_set(attributes, BgAttributes::Priority as u8),
+                },
             );
 
             pixels_drawn += pixels_to_draw;
         }
     }
 
+    /// One line of the BG or window tile at `map_address`, along with its CGB attributes
+    fn bg_tile_line(&self, map_address: u16, line: u16) -> (u16, u8) {
+        let tile_index = self.read_vram(0, map_address);
+        let attributes = if self.cgb_mode {
+            self.read_vram(1, map_address)
+        } else {
+            0
+        };
+
+        let tile_address = if is_set(self.lcdc, LCDCBits::BgWindowTiles as u8) {
+            // 8000 method
+            VRAM_BASE_ADDRESS + tile_index as u16 * BYTES_PER_TILE as u16
+        } else {
+            // 8800 method
+            0x9000u16.wrapping_add((tile_index as i8 as i16 * BYTES_PER_TILE as i16) as u16)
+        };
+
+        let line = if is_set(attributes, BgAttributes::YFlip as u8) {
+            BASE_TILE_WIDTH as u16 - line - 1
+        } else {
+            line
+        };
+        let bank = is_set(attributes, BgAttributes::Bank as u8) as usize;
+        let address = tile_address + line * BYTES_PER_LINE as u16;
+
+        let mut p1 = self.read_vram(bank, address);
+        let mut p2 = self.read_vram(bank, address + 1);
+        if is_set(attributes, BgAttributes::XFlip as u8) {
+            p1 = p1.reverse_bits();
+            p2 = p2.reverse_bits();
+        }
+
+        (PPU::compose_pixels(p1, p2), attributes)
+    }
+
     fn compose_pixels(first: u8, second: u8) -> u16 {
         let mut res = 0;
         for i in 0..8 {
@@ -442,7 +659,7 @@ impl PPU {
     }
 
     fn draw_window(&mut self) {
-        if !is_set(self.lcdc, LCDCBits::BgWindowEnable as u8)
+        if (!self.cgb_mode && !is_set(self.lcdc, LCDCBits::BgWindowEnable as u8))
             || !is_set(self.lcdc, LCDCBits::WindowEnable as u8)
         {
             return;
@@ -461,13 +678,6 @@ impl PPU {
             0x9800
         };
 
-        let tile_data_flag = is_set(self.lcdc, LCDCBits::BgWindowTiles as u8);
-        let tile_data: u16 = if tile_data_flag {
-            VRAM_BASE_ADDRESS
-        } else {
-            0x9000
-        };
-
         let tile_y = self.window_line_counter as usize / BASE_TILE_WIDTH;
         let tile_pixel_offset_y = self.window_line_counter as u16 % BASE_TILE_WIDTH as u16;
 
@@ -481,21 +691,8 @@ impl PPU {
             let tile_x = win_pixel_x / BASE_TILE_WIDTH;
 
             let tile_index = tile_y * TILE_MAP_WIDTH + tile_x;
-            let tile_data_index = self.read_byte(window_map + tile_index as u16);
-
-            let tile_address = if tile_data_flag {
-                // 8000 method
-                tile_data + tile_data_index as u16 * BYTES_PER_TILE as u16
-            } else {
-                // 8800 method
-                tile_data
-                    .wrapping_add((tile_data_index as i8 as i16 * BYTES_PER_TILE as i16) as u16)
-            };
-
-            let pixels = PPU::compose_pixels(
-                self.read_byte(tile_address + tile_pixel_offset_y * BYTES_PER_LINE as u16),
-                self.read_byte(tile_address + tile_pixel_offset_y * BYTES_PER_LINE as u16 + 1),
-            );
+            let (pixels, attributes) =
+                self.bg_tile_line(window_map + tile_index as u16, tile_pixel_offset_y);
 
             let start_x_offset = win_pixel_x % BASE_TILE_WIDTH;
             let pixels_to_draw = (BASE_TILE_WIDTH - start_x_offset).min(GB_SCREEN_WIDTH - x);
@@ -505,8 +702,10 @@ impl PPU {
                 pixels,
                 start_x_offset,
                 pixels_to_draw,
-                self.bgp,
-                None,
+                self.bg_colors(attributes),
+                Layer::Background {
+                    priority: is_set(attributes, BgAttributes::Priority as u8),
+                },
             );
 
             x += pixels_to_draw;
@@ -520,44 +719,94 @@ impl PPU {
         pixels: u16,
         pixels_start_offset: usize,
         pixels_to_draw: usize,
-        palette: Palette,
-        priority: Option<bool>,
+        colors: TileColors,
+        layer: Layer,
     ) {
         for i in pixels_start_offset..pixels_start_offset + pixels_to_draw {
             let shift = 2 * (BASE_TILE_WIDTH - i - 1);
             let color_index = (pixels >> shift & 0b11) as u8;
             let pixel_address = frame_base + i - pixels_start_offset;
-
-            if Some(true) == priority
-                && self.frame[pixel_address] != self.get_color_from_palette(self.bgp, 0)
-            {
-                continue;
-            }
-
-            let color = self.get_color_from_palette(palette, color_index);
-            if priority.is_some() {
-                if color_index != 0 {
-                    self.frame[pixel_address] = color;
+            let x = pixel_address % GB_SCREEN_WIDTH;
+
+            match layer {
+                Layer::Background { priority } => {
+                    self.bg_line[x] = BgPixel {
+                        color_index,
+                        priority,
+                    };
+                }
+                Layer::Sprite { behind_background } => {
+                    // color 0 is transparent for sprites
+                    if color_index == 0 || self.is_behind_background(x, behind_background) {
+                        continue;
+                    }
                 }
-            } else {
-                self.frame[pixel_address] = color;
             }
+
+            self.frame[pixel_address] = colors.colors[color_index as usize];
+            self.frame_shades[pixel_address] = colors.shades[color_index as usize];
+        }
+    }
+
+    /// Whether the background hides a sprite pixel at `x`. Only BG colors 1-3 can.
+    fn is_behind_background(&self, x: usize, behind_background: bool) -> bool {
+        let bg = self.bg_line[x];
+        if bg.color_index == 0 {
+            return false;
+        }
+
+        if self.cgb_mode {
+            // clearing LCDC bit 0 puts sprites over everything
+            is_set(self.lcdc, LCDCBits::BgWindowEnable as u8) && (behind_background || bg.priority)
+        } else {
+            behind_background
+        }
+    }
+
+    fn bg_colors(&self, attributes: u8) -> TileColors {
+        if self.cgb_mode {
+            TileColors::cgb(self.bg_palettes.colors(attributes & CGB_PALETTE_MASK))
+        } else {
+            self.dmg_colors(self.bgp, &self.bg_palettes, 0)
         }
     }
 
-    fn get_color_from_palette(&self, palette: Palette, color_index: u8) -> Color {
-        let color_id = (palette >> (color_index * 2) & 0b11) as u8;
-        self.palette[color_id as usize]
+    /// Colors for a DMG palette register. In compatibility mode, its shades come from
+    /// palette `number` of `compat_palettes`.
+    fn dmg_colors(
+        &self,
+        palette: Palette,
+        compat_palettes: &ColorPalettes,
+        number: u8,
+    ) -> TileColors {
+        let colors = if self.dmg_compat {
+            compat_palettes.colors(number)
+        } else {
+            self.palette
+        };
+        let shades = [0, 1, 2, 3].map(|color_index| palette >> (color_index * 2) & 0b11);
+        TileColors {
+            colors: shades.map(|shade| colors[shade as usize]),
+            shades,
+        }
     }
 
     pub fn pixel_data(&self) -> &[u8] {
         self.display.as_flattened()
     }
+
+    /// The DMG shade (0 - 3) of each pixel of the last frame
+    pub(crate) fn shade_data(&self) -> &[u8] {
+        &self.display_shades
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::boot::compat_palettes::ManualPalette;
+
+    const BG: Layer = Layer::Background { priority: false };
 
     #[test]
     fn test_compose_pixels() {
@@ -571,7 +820,14 @@ mod test {
         let intflag = Rc::new(RefCell::new(0));
         let mut ppu = PPU::new(intflag.clone());
         let palettte = 0b11100100;
-        ppu.draw_pixels(0, 0b0010111111111000, 0, 8, palettte, None);
+        ppu.draw_pixels(
+            0,
+            0b0010111111111000,
+            0,
+            8,
+            ppu.dmg_colors(palettte, &ppu.bg_palettes, 0),
+            BG,
+        );
 
         assert_eq!(
             ppu.frame[0..8],
@@ -579,7 +835,14 @@ mod test {
         );
 
         let mut ppu = PPU::new(intflag.clone());
-        ppu.draw_pixels(0, 0b0010111111111000, 0, 2, palettte, None);
+        ppu.draw_pixels(
+            0,
+            0b0010111111111000,
+            0,
+            2,
+            ppu.dmg_colors(palettte, &ppu.bg_palettes, 0),
+            BG,
+        );
 
         assert_eq!(
             ppu.frame[0..2],
@@ -587,11 +850,94 @@ mod test {
         );
 
         let mut ppu = PPU::new(intflag.clone());
-        ppu.draw_pixels(0, 0b0010111111111000, 2, 2, palettte, None);
+        ppu.draw_pixels(
+            0,
+            0b0010111111111000,
+            2,
+            2,
+            ppu.dmg_colors(palettte, &ppu.bg_palettes, 0),
+            BG,
+        );
 
         assert_eq!(
             ppu.frame[0..2],
             [0b11, 0b11].map(|id| MONOCHROME_PALETTE[id])
         );
     }
+
+    #[test]
+    fn test_color_palettes() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(0)));
+        // palette 1, color 0, auto increment
+        ppu.write_byte(0xFF68, 0x88);
+        for byte in [0x1F, 0x00, 0xE0, 0x03, 0x00, 0x7C, 0x10, 0x42] {
+            ppu.write_byte(0xFF69, byte);
+        }
+        assert_eq!(ppu.read_byte(0xFF68), 0xD0);
+
+        assert_eq!(
+            ppu.bg_palettes.colors(1),
+            [
+                [0xFF, 0x00, 0x00, 0xFF],
+                [0x00, 0xFF, 0x00, 0xFF],
+                [0x00, 0x00, 0xFF, 0xFF],
+                [0x84, 0x84, 0x84, 0xFF],
+            ]
+        );
+
+        ppu.write_byte(0xFF6A, 0x3F);
+        ppu.write_byte(0xFF6B, 0x12);
+        ppu.write_byte(0xFF6B, 0x34);
+        assert_eq!(ppu.read_byte(0xFF6A), 0x7F);
+        assert_eq!(ppu.read_byte(0xFF6B), 0x34);
+    }
+
+    #[test]
+    fn test_cgb_sprite_priority() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(0)));
+        ppu.set_cgb_mode(true);
+        let colors = TileColors::cgb([[0; 4], [1; 4], [2; 4], [3; 4]]);
+        let sprite = Layer::Sprite {
+            behind_background: false,
+        };
+
+        // BG attribute priority hides sprites over BG colors 1-3
+        ppu.draw_pixels(
+            0,
+            0b0001,
+            6,
+            2,
+            colors,
+            Layer::Background { priority: true },
+        );
+        ppu.draw_pixels(0, 0b1111, 6, 2, colors, sprite);
+        assert_eq!(ppu.frame[0..2], [[3; 4], [1; 4]]);
+
+        // unless LCDC bit 0 is cleared
+        ppu.lcdc = reset_bit(ppu.lcdc, LCDCBits::BgWindowEnable as u8);
+        ppu.draw_pixels(0, 0b1111, 6, 2, colors, sprite);
+        assert_eq!(ppu.frame[0..2], [[3; 4], [3; 4]]);
+    }
+
+    #[test]
+    fn test_dmg_compat_colors() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(0)));
+        ppu.set_compat_palette(&ManualPalette::Grayscale.palette());
+        // DMG palettes are only colorized in compatibility mode
+        assert_eq!(
+            ppu.dmg_colors(0b11100100, &ppu.obj_palettes, 1).colors,
+            MONOCHROME_PALETTE
+        );
+
+        ppu.set_dmg_compat(true);
+        assert_eq!(
+            ppu.dmg_colors(0b00011011, &ppu.obj_palettes, 1).colors,
+            [
+                [0x00, 0x00, 0x00, 0xFF],
+                [0x52, 0x52, 0x52, 0xFF],
+                [0xA5, 0xA5, 0xA5, 0xFF],
+                [0xFF, 0xFF, 0xFF, 0xFF],
+            ]
+        );
+    }
 }
@@ -0,0 +1,554 @@
+use std::cmp::Ordering;
+
+use crate::{
+    ppu::{Color, GB_SCREEN_HEIGHT, GB_SCREEN_WIDTH, rgb555_to_rgba},
+    utils::is_set,
+};
+
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+// the GB screen sits in the middle of the border
+const SCREEN_X: usize = (SGB_SCREEN_WIDTH - GB_SCREEN_WIDTH) / 2;
+const SCREEN_Y: usize = (SGB_SCREEN_HEIGHT - GB_SCREEN_HEIGHT) / 2;
+
+const PACKET_SIZE: usize = 16;
+const PACKET_BITS: usize = PACKET_SIZE * 8;
+
+// palettes are assigned per 8x8 cell of the GB screen
+const CELL_SIZE: usize = 8;
+const CELLS_X: usize = GB_SCREEN_WIDTH / CELL_SIZE;
+const CELLS_Y: usize = GB_SCREEN_HEIGHT / CELL_SIZE;
+
+// CHR_TRN and PCT_TRN send 4 KiB by showing it on screen as 256 tiles
+const VRAM_TRANSFER_SIZE: usize = 0x1000;
+const BYTES_PER_TILE: usize = 16;
+
+// The border is a SNES tile map with 4 bits per pixel tiles
+const BORDER_TILES: usize = 256;
+const BYTES_PER_BORDER_TILE: usize = 32;
+const BORDER_MAP_WIDTH: usize = 32;
+const BORDER_MAP_HEIGHT: usize = 28;
+const BORDER_PALETTES: usize = 4;
+const BORDER_PALETTES_OFFSET: usize = 0x800;
+
+// Command codes, from the top 5 bits of a command's first byte
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+// Shades of gray until a game sets its own palettes
+const DEFAULT_PALETTE: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mask {
+    Off,
+    // keep showing the last frame
+    Freeze,
+    Black,
+    Color0,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transfer {
+    // half of the border tiles
+    Tiles { upper: bool },
+    // the border tile map and palettes
+    Border,
+}
+
+/// Super Game Boy, which colorizes the screen and draws a border around it, driven by
+/// command packets games send through the joypad register
+pub struct Sgb {
+    // bits of the packet being received, None until a reset pulse starts one
+    packet_bits: Option<usize>,
+    // P14 and P15 have to go high between bits
+    ready_for_bit: bool,
+    packet: [u8; PACKET_SIZE],
+    // commands can span up to 7 packets
+    command: Vec<u8>,
+    packets_left: u8,
+
+    palettes: [[u16; 4]; 4],
+    attributes: [u8; CELLS_X * CELLS_Y],
+    mask: Mask,
+    // VRAM transfer to take from the next frame
+    transfer: Option<Transfer>,
+    // set by MLT_REQ, until the joypad takes it
+    player_count: Option<u8>,
+
+    border_tiles: Vec<u8>,
+    border_map: [u16; BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT],
+    border_palettes: [[u16; 16]; BORDER_PALETTES],
+
+    frame: Vec<Color>,
+}
+
+impl Sgb {
+    pub(crate) fn new() -> Self {
+        Sgb {
+            packet_bits: None,
+            ready_for_bit: false,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            packets_left: 0,
+
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: [0; CELLS_X * CELLS_Y],
+            mask: Mask::Off,
+            transfer: None,
+            player_count: None,
+
+            border_tiles: vec![0; BORDER_TILES * BYTES_PER_BORDER_TILE],
+            border_map: [0; BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT],
+            border_palettes: [[0; 16]; BORDER_PALETTES],
+
+            frame: vec![[0, 0, 0, 0xFF]; SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT],
+        }
+    }
+
+    /// Packets are sent a bit at a time by pulling P14 (a 0) or P15 (a 1) low,
+    /// after a reset pulse pulling both low
+    pub(crate) fn write_joypad(&mut self, byte: u8) {
+        match byte & 0x30 {
+            0x00 => {
+                self.packet_bits = Some(0);
+                self.packet = [0; PACKET_SIZE];
+                self.ready_for_bit = false;
+            }
+            0x30 => self.ready_for_bit = true,
+            lines => {
+                if let Some(bits) = self.packet_bits
+                    && self.ready_for_bit
+                {
+                    self.ready_for_bit = false;
+                    // packets end with a stop bit
+                    if bits == PACKET_BITS {
+                        self.packet_bits = None;
+                        self.receive_packet();
+                        return;
+                    }
+
+                    if lines == 0x10 {
+                        self.packet[bits / 8] |= 1 << (bits % 8);
+                    }
+                    self.packet_bits = Some(bits + 1);
+                }
+            }
+        }
+    }
+
+    /// The number of players MLT_REQ asked for, if it was sent since the last call
+    pub(crate) fn take_player_count(&mut self) -> Option<u8> {
+        self.player_count.take()
+    }
+
+    /// Colorize a finished frame, given the DMG shade of each pixel
+    pub(crate) fn on_frame(&mut self, shades: &[u8]) {
+        if let Some(transfer) = self.transfer.take() {
+            let data = Sgb::vram_transfer_data(shades);
+            match transfer {
+                Transfer::Tiles { upper } => {
+                    let offset = upper as usize * VRAM_TRANSFER_SIZE;
+                    self.border_tiles[offset..offset + VRAM_TRANSFER_SIZE].copy_from_slice(&data);
+                }
+                Transfer::Border => {
+                    for (i, entry) in self.border_map.iter_mut().enumerate() {
+                        *entry = u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+                    }
+                    let palettes = &data[BORDER_PALETTES_OFFSET..];
+                    for (i, color) in self
+                        .border_palettes
+                        .as_flattened_mut()
+                        .iter_mut()
+                        .enumerate()
+                    {
+                        *color = u16::from_le_bytes([palettes[i * 2], palettes[i * 2 + 1]]);
+                    }
+                }
+            }
+        }
+
+        self.draw_screen(shades);
+        self.draw_border();
+    }
+
+    pub fn pixel_data(&self) -> &[u8] {
+        self.frame.as_flattened()
+    }
+
+    fn receive_packet(&mut self) {
+        if self.packets_left == 0 {
+            // the first byte also has the number of packets the command takes
+            self.packets_left = (self.packet[0] & 0x07).max(1);
+            self.command.clear();
+        }
+
+        self.command.extend_from_slice(&self.packet);
+        self.packets_left -= 1;
+        if self.packets_left == 0 {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, command: &[u8]) {
+        match command[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, command),
+            PAL23 => self.set_palettes(2, 3, command),
+            PAL03 => self.set_palettes(0, 3, command),
+            PAL12 => self.set_palettes(1, 2, command),
+            ATTR_BLK => self.attribute_blocks(command),
+            ATTR_LIN => self.attribute_lines(command),
+            ATTR_DIV => self.attribute_division(command),
+            ATTR_CHR => self.attribute_cells(command),
+            MLT_REQ => {
+                self.player_count = Some(match command[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                })
+            }
+            CHR_TRN => {
+                self.transfer = Some(Transfer::Tiles {
+                    upper: is_set(command[1], 0),
+                })
+            }
+            PCT_TRN => self.transfer = Some(Transfer::Border),
+            MASK_EN => {
+                self.mask = match command[1] & 0x03 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::Off,
+                }
+            }
+            // sound, system palettes and attribute files aren't emulated
+            _ => {}
+        }
+    }
+
+    fn set_palettes(&mut self, first: usize, second: usize, command: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([command[1 + i * 2], command[2 + i * 2]]);
+
+        // color 0 is shared by every palette
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for i in 1..4 {
+            self.palettes[first][i] = color(i);
+            self.palettes[second][i] = color(i + 3);
+        }
+    }
+
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        if x < CELLS_X && y < CELLS_Y {
+            self.attributes[y * CELLS_X + x] = palette & 0x03;
+        }
+    }
+
+    /// Rectangles, with separate palettes inside, on and outside their edges
+    fn attribute_blocks(&mut self, command: &[u8]) {
+        let count = command[1] as usize;
+        for block in command[2..].chunks_exact(6).take(count) {
+            let control = block[0] & 0x07;
+            let inside = block[1] & 0x03;
+            let edge = (block[1] >> 2) & 0x03;
+            let outside = (block[1] >> 4) & 0x03;
+            let (x1, y1, x2, y2) = (
+                block[2] as usize,
+                block[3] as usize,
+                block[4] as usize,
+                block[5] as usize,
+            );
+
+            // with only the inside or outside changed, the edge goes along with it
+            let edge = match control {
+                0x01 => Some(inside),
+                0x04 => Some(outside),
+                _ if is_set(control, 1) => Some(edge),
+                _ => None,
+            };
+
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+
+                    let palette = if on_edge {
+                        edge
+                    } else if within {
+                        is_set(control, 0).then_some(inside)
+                    } else {
+                        is_set(control, 2).then_some(outside)
+                    };
+                    if let Some(palette) = palette {
+                        self.set_attribute(x, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whole rows or columns of cells
+    fn attribute_lines(&mut self, command: &[u8]) {
+        let count = command[1] as usize;
+        for &line in command[2..].iter().take(count) {
+            let number = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0x03;
+            if is_set(line, 7) {
+                for x in 0..CELLS_X {
+                    self.set_attribute(x, number, palette);
+                }
+            } else {
+                for y in 0..CELLS_Y {
+                    self.set_attribute(number, y, palette);
+                }
+            }
+        }
+    }
+
+    /// The screen split in two by a row or column, which has its own palette
+    fn attribute_division(&mut self, command: &[u8]) {
+        let after = command[1] & 0x03;
+        let before = (command[1] >> 2) & 0x03;
+        let line = (command[1] >> 4) & 0x03;
+        let horizontal = is_set(command[1], 6);
+        let division = command[2] as usize;
+
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                let palette = match position.cmp(&division) {
+                    Ordering::Less => before,
+                    Ordering::Equal => line,
+                    Ordering::Greater => after,
+                };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    /// Individual cells, 4 to a byte, from a starting cell left to right or top to bottom
+    fn attribute_cells(&mut self, command: &[u8]) {
+        let (mut x, mut y) = (command[1] as usize, command[2] as usize);
+        let count = u16::from_le_bytes([command[3], command[4]]) as usize;
+        let vertical = is_set(command[5], 0);
+
+        for i in 0..count.min(CELLS_X * CELLS_Y) {
+            let Some(byte) = command.get(6 + i / 4) else {
+                break;
+            };
+            self.set_attribute(x, y, byte >> (6 - (i % 4) * 2));
+
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    /// Turn the first 256 tiles on screen back into 2 bit per pixel tile data
+    fn vram_transfer_data(shades: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; VRAM_TRANSFER_SIZE];
+        for (tile, bytes) in data.chunks_exact_mut(BYTES_PER_TILE).enumerate() {
+            let tile_x = (tile % CELLS_X) * CELL_SIZE;
+            let tile_y = (tile / CELLS_X) * CELL_SIZE;
+            for row in 0..CELL_SIZE {
+                for column in 0..CELL_SIZE {
+                    let shade = shades[(tile_y + row) * GB_SCREEN_WIDTH + tile_x + column];
+                    let bit = 7 - column;
+                    bytes[row * 2] |= (shade & 0x01) << bit;
+                    bytes[row * 2 + 1] |= ((shade >> 1) & 0x01) << bit;
+                }
+            }
+        }
+        data
+    }
+
+    fn draw_screen(&mut self, shades: &[u8]) {
+        if self.mask == Mask::Freeze {
+            return;
+        }
+
+        let backdrop = rgb555_to_rgba(self.palettes[0][0]);
+        for y in 0..GB_SCREEN_HEIGHT {
+            for x in 0..GB_SCREEN_WIDTH {
+                let color = match self.mask {
+                    Mask::Black => [0x00, 0x00, 0x00, 0xFF],
+                    Mask::Color0 => backdrop,
+                    _ => {
+                        let palette = self.attributes[(y / CELL_SIZE) * CELLS_X + x / CELL_SIZE];
+                        let shade = shades[y * GB_SCREEN_WIDTH + x];
+                        rgb555_to_rgba(self.palettes[palette as usize][shade as usize])
+                    }
+                };
+                self.frame[(SCREEN_Y + y) * SGB_SCREEN_WIDTH + SCREEN_X + x] = color;
+            }
+        }
+    }
+
+    /// Draw the border over the GB screen, which shows through its transparent pixels
+    fn draw_border(&mut self) {
+        let backdrop = rgb555_to_rgba(self.palettes[0][0]);
+
+        for map_y in 0..BORDER_MAP_HEIGHT {
+            for map_x in 0..BORDER_MAP_WIDTH {
+                let entry = self.border_map[map_y * BORDER_MAP_WIDTH + map_x];
+                let tile_offset = (entry & 0xFF) as usize * BYTES_PER_BORDER_TILE;
+                let tile = &self.border_tiles[tile_offset..tile_offset + BYTES_PER_BORDER_TILE];
+                // the border uses SNES palettes 4 - 7
+                let palette = &self.border_palettes[((entry >> 10) & 0x03) as usize];
+                let x_flip = entry & 0x4000 != 0;
+                let y_flip = entry & 0x8000 != 0;
+
+                for row in 0..CELL_SIZE {
+                    let tile_row = if y_flip { CELL_SIZE - row - 1 } else { row };
+                    for column in 0..CELL_SIZE {
+                        let bit = if x_flip { column } else { 7 - column };
+                        let plane = |offset: usize| (tile[offset] >> bit) & 0x01;
+                        let color_index = plane(tile_row * 2)
+                            | (plane(tile_row * 2 + 1) << 1)
+                            | (plane(16 + tile_row * 2) << 2)
+                            | (plane(16 + tile_row * 2 + 1) << 3);
+
+                        let x = map_x * CELL_SIZE + column;
+                        let y = map_y * CELL_SIZE + row;
+                        let on_screen = (SCREEN_X..SCREEN_X + GB_SCREEN_WIDTH).contains(&x)
+                            && (SCREEN_Y..SCREEN_Y + GB_SCREEN_HEIGHT).contains(&y);
+
+                        let pixel = &mut self.frame[y * SGB_SCREEN_WIDTH + x];
+                        if color_index != 0 {
+                            *pixel = rgb555_to_rgba(palette[color_index as usize]);
+                        } else if !on_screen {
+                            *pixel = backdrop;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn send_command(sgb: &mut Sgb, command: &[u8]) {
+        for packet in command.chunks(PACKET_SIZE) {
+            sgb.write_joypad(0x00);
+            sgb.write_joypad(0x30);
+            for i in 0..PACKET_BITS {
+                let bit = packet
+                    .get(i / 8)
+                    .is_some_and(|byte| is_set(*byte, (i % 8) as u8));
+                sgb.write_joypad(if bit { 0x10 } else { 0x20 });
+                sgb.write_joypad(0x30);
+            }
+            // stop bit
+            sgb.write_joypad(0x20);
+            sgb.write_joypad(0x30);
+        }
+    }
+
+    fn screen_pixel(sgb: &Sgb, x: usize, y: usize) -> Color {
+        sgb.frame[(SCREEN_Y + y) * SGB_SCREEN_WIDTH + SCREEN_X + x]
+    }
+
+    #[test]
+    fn test_palettes_and_attributes() {
+        let mut sgb = Sgb::new();
+        // PAL01: shared color 0 white, palette 1 colors 1 - 3 red
+        send_command(
+            &mut sgb,
+            &[
+                (PAL01 << 3) | 1,
+                0xFF,
+                0x7F,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0x1F,
+                0x00,
+                0x1F,
+                0x00,
+                0x1F,
+                0x00,
+            ],
+        );
+        assert_eq!(sgb.palettes[1], [0x7FFF, 0x001F, 0x001F, 0x001F]);
+
+        // ATTR_DIV: palette 1 below row 9
+        send_command(&mut sgb, &[(ATTR_DIV << 3) | 1, 0b0100_0001, 9]);
+        sgb.on_frame(&[1; GB_SCREEN_WIDTH * GB_SCREEN_HEIGHT]);
+        assert_eq!(screen_pixel(&sgb, 0, 0), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(
+            screen_pixel(&sgb, 0, 10 * CELL_SIZE),
+            [0xFF, 0x00, 0x00, 0xFF]
+        );
+
+        // MASK_EN: black
+        send_command(&mut sgb, &[(MASK_EN << 3) | 1, 2]);
+        sgb.on_frame(&[0; GB_SCREEN_WIDTH * GB_SCREEN_HEIGHT]);
+        assert_eq!(screen_pixel(&sgb, 0, 0), [0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_attribute_block() {
+        let mut sgb = Sgb::new();
+        // only the inside changes, which takes the edge along with it
+        send_command(
+            &mut sgb,
+            &[(ATTR_BLK << 3) | 1, 1, 0x01, 0b10_01_11, 2, 2, 4, 4],
+        );
+        assert_eq!(sgb.attributes[2 * CELLS_X + 2], 3);
+        assert_eq!(sgb.attributes[3 * CELLS_X + 3], 3);
+        assert_eq!(sgb.attributes[5 * CELLS_X + 5], 0);
+
+        send_command(
+            &mut sgb,
+            &[(ATTR_BLK << 3) | 1, 1, 0x06, 0b10_01_11, 2, 2, 4, 4],
+        );
+        assert_eq!(sgb.attributes[2 * CELLS_X + 2], 1);
+        assert_eq!(sgb.attributes[3 * CELLS_X + 3], 3);
+        assert_eq!(sgb.attributes[5 * CELLS_X + 5], 2);
+    }
+
+    #[test]
+    fn test_multiplayer_request() {
+        let mut sgb = Sgb::new();
+        send_command(&mut sgb, &[(MLT_REQ << 3) | 1, 0x01]);
+        assert_eq!(sgb.take_player_count(), Some(2));
+        assert_eq!(sgb.take_player_count(), None);
+    }
+
+    #[test]
+    fn test_vram_transfer_data() {
+        let mut shades = [0; GB_SCREEN_WIDTH * GB_SCREEN_HEIGHT];
+        // first row of the second tile: shades 3, 1, 2, 0...
+        shades[CELL_SIZE..CELL_SIZE + 3].copy_from_slice(&[3, 1, 2]);
+        let data = Sgb::vram_transfer_data(&shades);
+        assert_eq!(data[BYTES_PER_TILE], 0b1100_0000);
+        assert_eq!(data[BYTES_PER_TILE + 1], 0b1010_0000);
+    }
+}
@@ -25,7 +25,7 @@ impl Timer {
         Timer {
             clock: 0,
             tima_clock: 0,
-            div: 0xAB,
+            div: 0,
             tima: 0,
             tma: 0,
 
@@ -62,7 +62,7 @@ impl Timer {
 
     pub fn write_byte(&mut self, address: u16, byte: u8) {
         match address {
-            0xFF04 => self.div = 0x00,
+            0xFF04 => self.set_div(0x00),
             0xFF05 => self.tima = byte,
             0xFF06 => self.tma = byte,
             0xFF07 => {
@@ -79,6 +79,12 @@ impl Timer {
         }
     }
 
+    /// Set DIV, with the rest of the internal counter cleared
+    pub(crate) fn set_div(&mut self, div: u8) {
+        self.clock = (div as u16) << 8;
+        self.div = div;
+    }
+
     fn increment_clock(&mut self, amount: usize) {
         self.clock = self.clock.wrapping_add(amount as u16);
         self.div = (self.clock >> 8) as u8;
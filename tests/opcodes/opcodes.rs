@@ -7,7 +7,10 @@ use std::{
 };
 
 use gb_emulator::{
-    cartridge::{Cartridge, NoMBC},
+    cartridge::{
+        Cartridge, NoMBC,
+        header::{CartridgeHeader, HEADER_END},
+    },
     cpu::CPU,
     gb::GameBoy,
     mmu::MMU,
@@ -87,7 +90,9 @@ fn initialize_test(initial: &GBState) -> GameBoy {
     cpu.set_ime(initial.ime > 0);
 
     let cart = Cartridge {
-        title: "test".into(),
+        header: CartridgeHeader::parse(&[0; HEADER_END]).unwrap(),
+        has_battery: false,
+        save_path: None,
         mbc: Box::new(NoMBC::new()),
     };
     let mut mmu = MMU::new(cart, false);